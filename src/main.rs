@@ -7,149 +7,428 @@ extern crate argparse;
 extern crate notify_rust;
 extern crate ini;
 extern crate rodio;
+extern crate libc;
 
 pub mod pomorust;
 
-use std::env;
+use std::io;
+use std::io::Write;
 use std::process;
 
 use pomorust::config;
-use pomorust::actions::{parse, Command, ListingOption};
-use pomorust::model::{Context, Task, TaskModification};
+use pomorust::daemon;
+use pomorust::daemon::Request;
+use pomorust::error::Error;
+use pomorust::history::Outcome;
+use pomorust::output;
+use pomorust::output::OutputFormat;
+use pomorust::stats;
+use pomorust::stats::Report;
+use pomorust::tui;
+use pomorust::actions::{parse, ArchivingOption, Command, CommentOption, ListingOption, ListingView,
+                        LogOption, PlanningOption, RepairingOption, ShowingOption, StartingOption, StatsOption,
+                        StatusOption};
+use pomorust::model::{sort_tasks, Context, IdentificationError, Interruption, Note, Task,
+                      TaskFilter, TaskModification};
 use pomorust::utils;
 
-fn check_if_background_proc() -> bool {
-    match env::args().nth(1) {
-        Some(s) => { 
-            if s == "new_pomodoro" {
-                return true
-            }
-        },
-        _ => ()
+fn main() {
+    match run() {
+        Ok(()) => (),
+        Err(e) => {
+            writeln!(&mut io::stderr(), "{}", e).unwrap();
+            process::exit(e.exit_code());
+        }
     }
-    return false
 }
 
-fn main() {
-    let mut context = config::create_context();
-    if check_if_background_proc() {
-        start_task(&mut context, env::args().nth(2).expect("Invalid background call"))
-    }
-    else {
-        match parse() {
-            Command::TaskNew(Some(t)) => { add_task(&mut context, t); },
-            Command::TaskList(Some(o)) => { list_task(context, o); },
-            Command::TaskStart(Some(t)) => { utils::run_background_process(t); },
-            Command::TaskDone(Some(t)) => { mark_as_done(&mut context, t); },
-            Command::Status => { context.display_status(); },
-            Command::TaskModify(Some((i, m))) => { modify_task(&mut context, i, m); },
-            _ => panic!("Invalid command")
-        }
+fn run() -> Result<(), Error> {
+    // Commands talking to the daemon must not hold the lock on the
+    // task file: the daemon needs it to answer them.
+    let command = match parse() {
+        Command::Daemon => return daemon::serve(),
+        Command::Tui => return tui::run(),
+        Command::TaskStart(Some((t, o))) => return start_pomodoro(t, o),
+        Command::TaskStop => return stop_pomodoro(),
+        Command::TaskPause => return pause_pomodoro(),
+        Command::TaskResume => return resume_pomodoro(),
+        Command::Status(Some(o)) => return display_status(o),
+        Command::TaskComment(Some(o)) => match o.edit {
+            true => return edit_notes(o.identifier),
+            false => Command::TaskComment(Some(o))
+        },
+        c => c
+    };
+    let _lock = try!(config::lock_task_file());
+    let mut context = try!(daemon::create_context());
+    let undo_point = match command.can_be_undone() {
+        true => Some(try!(config::undo_point())),
+        false => None
+    };
+    let result = match command {
+        Command::TaskNew(Some(t)) => add_task(&mut context, t),
+        Command::TaskList(Some(o)) => list_task(context, o),
+        Command::TaskDone(Some(t)) => mark_as_done(&mut context, t),
+        Command::TaskModify(Some((i, m))) => modify_task(&mut context, i, m),
+        Command::Interrupt(Some(i)) => note_interruption(&mut context, i),
+        Command::Log(Some(o)) => display_log(&context, o),
+        Command::Stats(Some(o)) => display_stats(context, o),
+        Command::TaskPlan(Some(o)) => plan_tasks(&mut context, o),
+        Command::TaskComment(Some(o)) => comment_task(&mut context, o),
+        Command::TaskNotes(Some(i)) => list_notes(&context, i),
+        Command::TaskShow(Some(o)) => show_task(&context, o),
+        Command::TaskDelete(Some(i)) => delete_task(&mut context, i),
+        Command::TaskReopen(Some(i)) => reopen_task(&mut context, i),
+        Command::TaskArchive(Some(o)) => archive_tasks(&mut context, o),
+        Command::Repair(Some(o)) => repair(&mut context, o),
+        Command::Undo => undo(),
+        c => Err(Error::InvalidCommand(format!("{:?}", c)))
+    };
+    if let (&Ok(()), Some(p)) = (&result, undo_point) {
+        try!(p.save());
     }
+    result
 }
 
-fn add_task(context: &mut Context,  task: Task) {
+fn add_task(context: &mut Context,  mut task: Task) -> Result<(), Error> {
     println!("New task :\t{}", task.to_string());
+    if task.is_unplanned() {
+        task.set_captured_during(context.current_pomodoro());
+    }
+    let archive = try!(config::read_archive());
+    let known_tasks = context.tasks.iter().chain(archive.iter()).collect::<Vec<&Task>>();
+    for tag in task.tags() {
+        if let Some(estimate) = stats::suggested_estimate(&known_tasks, tag) {
+            println!("Recent tasks tagged {} took {} pomodori on average \
+                      (estimated : {}).", tag, estimate, task.pomodori_estimate());
+        }
+    }
     context.add_task(task);
-    config::write_task_file(&context).unwrap();
+    config::write_task_file(&context)
 }
 
-fn list_task(context: Context, opt: ListingOption) {
-    let to_iterate = match opt.only_current {
-        true => context.get_current_tasks(),
-        false => context.get_all_tasks()
+fn list_task(context: Context, opt: ListingOption) -> Result<(), Error> {
+    // Finished tasks are on no sheet: looking for them in today's
+    // would never find any.
+    let dates = [&opt.started_since, &opt.started_until, &opt.ended_since, &opt.ended_until];
+    let view = match opt.view {
+        ListingView::Today if opt.state.is_some() || dates.iter().any(|x| x.is_some()) =>
+            ListingView::All,
+        v => v
     };
-    for t in to_iterate {
-        println!("{}", t.to_list_line());
+    let filter = TaskFilter { tags: opt.tags,
+                              project: opt.project,
+                              state: opt.state,
+                              started_since: try!(utils::parse_maybe_date(opt.started_since)),
+                              started_until: try!(utils::parse_maybe_date(opt.started_until)),
+                              ended_since: try!(utils::parse_maybe_date(opt.ended_since)),
+                              ended_until: try!(utils::parse_maybe_date(opt.ended_until)),
+                              text: opt.search };
+    let today = chrono::Local::now().naive_local().date();
+    let mut to_iterate = match view {
+        ListingView::Today => context.get_today_tasks(today),
+        ListingView::Inventory => context.get_inventory_tasks(today),
+        ListingView::Current => context.get_current_tasks(),
+        ListingView::All => context.get_all_tasks()
+    };
+    to_iterate.retain(|x| filter.matches(x));
+    sort_tasks(&mut to_iterate, opt.sort);
+    if opt.reverse {
+        to_iterate.reverse();
     }
+    if view == ListingView::Today && opt.format == OutputFormat::Plain {
+        list_today_sheet(&to_iterate, today);
+    } else {
+        output::print_tasks(&to_iterate, opt.format);
+    }
+    Ok(())
 }
 
-fn start_task(context: &mut Context, identifier: String) {
-    context.timer = Some(chrono::Local::now());
-    if context.has_ongoing_task() {
-        println!("You are already doing a task ! Mark it as done if you're over before starting a new one.");
-        process::exit(0);
+fn list_today_sheet(sheet: &[&Task], today: chrono::NaiveDate) {
+    if sheet.is_empty() {
+        println!("Nothing planned for today. See the inventory with \"list --inventory\", \
+                  and plan tasks with \"plan\".");
+        return;
+    }
+    for t in sheet.iter().filter(|x| !x.is_carried_over(today) && !x.is_unplanned_on(today)) {
+        println!("{}", t.to_list_line());
     }
-    if context.is_valid_identifier(&identifier).is_ok() {
-		before_pomodoro(context, &identifier);
+    let unplanned = sheet.iter().filter(|x| x.is_unplanned_on(today)).collect::<Vec<_>>();
+    if !unplanned.is_empty() {
+        println!("Unplanned & urgent :");
+        for t in unplanned {
+            match t.captured_during() {
+                Some(d) => println!("{} (during the pomodoro of {})",
+                                    t.to_list_line(), d.format("%H:%M")),
+                None => println!("{}", t.to_list_line())
+            }
+        }
+    }
+    let carried_over = sheet.iter()
+        .filter(|x| x.is_carried_over(today) && !x.is_unplanned_on(today))
+        .collect::<Vec<_>>();
+    if !carried_over.is_empty() {
+        println!("Carried over from previous days :");
+        for t in carried_over {
+            println!("{}", t.to_list_line());
+        }
+    }
+}
+
+fn display_status(opt: StatusOption) -> Result<(), Error> {
+    let mut context = {
+        let _lock = try!(config::lock_task_file());
+        try!(daemon::create_context())
+    };
+    try!(daemon::sync_timer(&mut context));
+    if opt.short {
+        let template = opt.template.as_ref().unwrap_or(&context.status_template);
+        println!("{}", output::render_status_line(&context, template));
     } else {
-        panic!("Not a valid identifier !");
+        output::print_status(&context, opt.format);
     }
-    config::write_task_file(context).unwrap();
-	do_pomodoro(identifier);
+    Ok(())
 }
 
-fn before_pomodoro(context: &mut Context, identifier: &str) {
-    {
-        let task = context.get_task(identifier);
-        task.before_starting_pomodoro();
-        println!("Starting task : {}", task.to_string());
+fn start_pomodoro(identifier: String, opt: StartingOption) -> Result<(), Error> {
+    let (request, description) = {
+        let _lock = try!(config::lock_task_file());
+        let context = try!(daemon::create_context());
+        let durations = context.durations.overridden_by(opt.work_duration,
+                                                        opt.break_duration,
+                                                        opt.long_break_duration);
+        let task = try!(context.find_task(&identifier));
+        (Request::Start(task.uuid.to_string(), durations.work, durations.short_break,
+                        durations.long_break),
+         task.to_string())
+    };
+    try!(daemon::send_or_spawn(&request));
+    println!("Starting task : {}", description);
+    Ok(())
+}
+
+/// Ask the daemon to void the pomodoro, or to end the break.
+fn stop_pomodoro() -> Result<(), Error> {
+    println!("{}", daemon::answer_message(&try!(daemon::stop_timer())));
+    Ok(())
+}
+
+fn pause_pomodoro() -> Result<(), Error> {
+    let answer = try!(daemon::send(&Request::Pause));
+    println!("{}", daemon::answer_message(answer.first().map_or("", |x| &x[..])));
+    Ok(())
+}
+
+fn resume_pomodoro() -> Result<(), Error> {
+    let answer = try!(daemon::send(&Request::Resume));
+    println!("{}", daemon::answer_message(answer.first().map_or("", |x| &x[..])));
+    Ok(())
+}
+
+/// Clear a timer left behind by a crash or a reboot, when reading
+/// the task file did not (see `stale_timer` in the config file).
+fn repair(context: &mut Context, opt: RepairingOption) -> Result<(), Error> {
+    if context.timer.is_none() && !context.has_ongoing_task() {
+        println!("Nothing to repair.");
+        return Ok(());
+    }
+    let owner_running = context.timer_pid.map_or(false, utils::is_pomorust_process);
+    if !context.has_stale_timer(owner_running) {
+        println!("The timer is still running : use stop to void the pomodoro.");
+        return Ok(());
     }
-    // ... and save this state.
-    config::write_task_file(context).unwrap();
+    println!("{}", match try!(config::repair_timer(context, opt.count)) {
+        Some(Outcome::Completed) => "Pomodoro counted as done.",
+        Some(Outcome::Interrupted) => "Pomodoro voided.",
+        None => "Timer cleared."
+    });
+    Ok(())
 }
 
-fn do_pomodoro(identifier: String) {
-    // Only then, do the pomodoro itself.
-    utils::wait_for(25);
+fn note_interruption(context: &mut Context, interruption: Interruption) -> Result<(), Error> {
+    let mark = interruption.kind.to_mark();
+    try!(context.add_interruption(interruption));
+    println!("Interruption noted {}", mark);
+    config::write_task_file(&context)
+}
+
+fn display_log(context: &Context, opt: LogOption) -> Result<(), Error> {
+    let since = try!(utils::parse_maybe_date(opt.since));
+    let until = try!(utils::parse_maybe_date(opt.until));
+    let mut archived = Context::default();
+    archived.tasks = try!(config::read_archive());
+    let task = match opt.task {
+        Some(t) => Some(match context.find_task(&t) {
+            Ok(task) => task.uuid,
+            // Archived tasks keep their history.
+            Err(Error::Identification(_, IdentificationError::NoMatch)) =>
+                try!(archived.find_task(&t)).uuid,
+            Err(e) => return Err(e)
+        }),
+        None => None
+    };
+    for entry in try!(config::read_history()) {
+        if entry.matches(since, until, task.as_ref()) {
+            let description = context.tasks.iter().chain(archived.tasks.iter())
+                .find(|x| x.uuid == entry.task)
+                .map_or(entry.task.to_string(), |x| x.description.clone());
+            println!("{}", entry.to_log_line(&description));
+        }
+    }
+    Ok(())
+}
+
+fn display_stats(context: Context, opt: StatsOption) -> Result<(), Error> {
+    let since = try!(utils::parse_maybe_date(opt.since));
+    let until = try!(utils::parse_maybe_date(opt.until));
+    let history = try!(config::read_history());
+    let archive = try!(config::read_archive());
+    let tasks = archive.iter().chain(context.tasks.iter()).collect::<Vec<&Task>>();
+    Report::new(&tasks, &history, since, until).display();
+    Ok(())
+}
 
-    // Now that we are here, the context might have changed:
-    // user could have added some tasks, for instance. Reload
-    // context.
-    let mut updated_context = config::create_context();
+fn reopen_task(context: &mut Context, identifier: String) -> Result<(), Error> {
     {
-        let mut worked_upon_task = updated_context.get_task(&identifier);
-        worked_upon_task.after_doing_pomodoro();
-    }
-    updated_context.increment_pomodoro_count();
-    updated_context.pause = true;
-    updated_context.timer = Some(chrono::Local::now());
-    config::write_task_file(&updated_context).unwrap();
-    if updated_context.should_be_long_pause() {
-        pause(&updated_context, 30);
-    } else {
-        pause(&updated_context, 5);
+        let task = try!(context.get_task(&identifier));
+        if !task.reopen() {
+            println!("Task is not finished :\t{}", task.to_string());
+            return Ok(());
+        }
+        println!("Reopened task :\t{}", task.to_string());
     }
-    updated_context.pause = false;
-    updated_context.timer = None;
-    config::write_task_file(&updated_context).unwrap();
+    config::write_task_file(&context)
 }
 
-fn pause(context: &Context, minutes: u16) {
-    notify_according_to_context(&context, "Pomodoro done !", &format!("Take a {} minute break !", minutes));
-    utils::wait_for(minutes);
-    notify_according_to_context(&context, "Break is over !", "Start a new task");
+fn delete_task(context: &mut Context, identifier: String) -> Result<(), Error> {
+    let task = try!(context.remove_task(&identifier));
+    println!("Deleted task :\t{}", task.to_string());
+    config::write_task_file(&context)
 }
 
-fn notify_according_to_context(context: &Context, notif_title: &str, notif_text: &str) {
-    if context.use_notification {
-        utils::notify(notif_title, notif_text);
-    } else {
-        println!("{} {}", notif_title, notif_text);
+fn archive_tasks(context: &mut Context, opt: ArchivingOption) -> Result<(), Error> {
+    let finished_before = match opt.older_than {
+        Some(age) => Some(chrono::Local::now() - try!(utils::parse_age(&age))),
+        None => None
+    };
+    let archived = context.take_finished_tasks(finished_before);
+    if archived.is_empty() {
+        println!("No task to archive.");
+        return Ok(());
+    }
+    try!(config::archive_tasks(&context, &archived));
+    println!("{} tasks archived.", archived.len());
+    Ok(())
+}
+
+fn undo() -> Result<(), Error> {
+    try!(config::undo());
+    println!("Tasks are back as they were before the last command.");
+    Ok(())
+}
+
+fn plan_tasks(context: &mut Context, opt: PlanningOption) -> Result<(), Error> {
+    let day = match opt.unplan {
+        true => None,
+        false => Some(try!(utils::parse_maybe_date(opt.day))
+                      .unwrap_or(chrono::Local::now().naive_local().date()))
+    };
+    for identifier in opt.identifiers.iter() {
+        let task = try!(context.get_task(identifier));
+        task.plan(day);
+        match day {
+            Some(d) => println!("Planned for {} : {}", d, task.to_string()),
+            None => println!("Back to the inventory : {}", task.to_string())
+        }
     }
-    if context.use_sound {
-        utils::ding();
+    config::write_task_file(&context)
+}
+
+fn comment_task(context: &mut Context, opt: CommentOption) -> Result<(), Error> {
+    {
+        let task = try!(context.get_task(&opt.identifier));
+        if let Some(n) = opt.remove {
+            if !task.remove_note(n) {
+                return Err(Error::InvalidCommand(format!("no note {} on this task", n)));
+            }
+        } else {
+            let text = try!(opt.text.ok_or(Error::InvalidCommand("missing note text".to_string())));
+            match opt.replace {
+                Some(n) => if !task.replace_note(n, &text) {
+                    return Err(Error::InvalidCommand(format!("no note {} on this task", n)));
+                },
+                None => task.add_note(&text)
+            }
+        }
+        println!("{} notes on : {}", task.notes().len(), task.to_string());
     }
+    config::write_task_file(&context)
 }
 
+/// Edit the notes of a task in $EDITOR. The task file is not locked
+/// meanwhile, so that the daemon goes on timing.
+fn edit_notes(identifier: String) -> Result<(), Error> {
+    let (uuid, text) = {
+        let _lock = try!(config::lock_task_file());
+        let context = try!(daemon::create_context());
+        let task = try!(context.find_task(&identifier));
+        (task.uuid.to_string(), Note::to_editable_text(task.notes()))
+    };
+    let edited = try!(utils::edit_in_editor(&text));
+    let _lock = try!(config::lock_task_file());
+    let mut context = try!(daemon::create_context());
+    let undo_point = try!(config::undo_point());
+    {
+        let task = try!(context.get_task(&uuid));
+        task.set_notes(Note::from_editable_text(&edited));
+        println!("{} notes on : {}", task.notes().len(), task.to_string());
+    }
+    try!(config::write_task_file(&context));
+    undo_point.save()
+}
 
-fn mark_as_done(context: &mut Context, identifier: String) {
-    if context.is_valid_identifier(&identifier).is_ok() {
-        {
-            let task = context.get_task(&identifier);
-            task.finish();
+fn list_notes(context: &Context, identifier: String) -> Result<(), Error> {
+    let task = try!(context.find_task(&identifier));
+    for (i, note) in task.notes().iter().enumerate() {
+        println!("{}. [{}] {}", i + 1, note.date.format("%Y-%m-%d %H:%M"),
+                 note.text.replace("\n", "\n   "));
+    }
+    Ok(())
+}
+
+fn show_task(context: &Context, opt: ShowingOption) -> Result<(), Error> {
+    let task = try!(context.find_task(&opt.identifier));
+    output::print_task(task, opt.format);
+    Ok(())
+}
+
+fn mark_as_done(context: &mut Context, identifier: String) -> Result<(), Error> {
+    let tags = {
+        let task = try!(context.get_task(&identifier));
+        task.finish();
+        if task.pomodori_estimate() > 0 {
+            println!("Done in {} pomodori, estimated {} ({:+}).", task.pomodori_count(),
+                     task.pomodori_estimate(), task.estimate_error());
+        }
+        task.tags().to_vec()
+    };
+    let archive = try!(config::read_archive());
+    let known_tasks = context.tasks.iter().chain(archive.iter()).collect::<Vec<&Task>>();
+    for tag in tags {
+        let accuracy = stats::tag_accuracy(&known_tasks, &tag);
+        if let Some(ratio) = accuracy.ratio() {
+            println!("Last {} tasks tagged {} took {:.0}% of their estimate.",
+                     accuracy.tasks, tag, ratio * 100.0);
         }
-    } else {
-        panic!("Could not identify task");
     }
-    config::write_task_file(&context).unwrap();
+    config::write_task_file(&context)
 }
 
 fn modify_task(context: &mut Context, identifier: String,
-               modification: TaskModification) {
-    if context.is_valid_identifier(&identifier).is_ok() {
-        let task = context.get_task(&identifier);
+               modification: TaskModification) -> Result<(), Error> {
+    {
+        let task = try!(context.get_task(&identifier));
         task.modify(modification);
     }
-    config::write_task_file(&context).unwrap();
+    config::write_task_file(&context)
 }
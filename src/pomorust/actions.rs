@@ -2,24 +2,149 @@ use std::process::exit;
 use std::str::FromStr;
 use std::io::{stdout, stderr};
 
-use argparse::{ArgumentParser, Store, StoreOption, List, StoreFalse};
-use pomorust::model::{Task, TaskModification};
+use argparse::{ArgumentParser, Store, StoreOption, StoreConst, StoreTrue, List, Collect};
+use pomorust::model::{Interruption, InterruptionKind, SortKey, Task, TaskModification, TaskState};
+use pomorust::output::OutputFormat;
 
 
 #[derive(Debug)]
 pub enum Command {
-    TaskStart(Option<String>),
+    TaskStart(Option<(String, StartingOption)>),
     TaskNew(Option<Task>),
+    TaskJot,
     TaskDone(Option<String>),
     TaskList(Option<ListingOption>),
     TaskModify(Option<(String, TaskModification)>),
-    Status
+    TaskStop,
+    TaskPause,
+    TaskResume,
+    Interrupt(Option<Interruption>),
+    Log(Option<LogOption>),
+    Stats(Option<StatsOption>),
+    TaskPlan(Option<PlanningOption>),
+    TaskComment(Option<CommentOption>),
+    TaskNotes(Option<String>),
+    TaskShow(Option<ShowingOption>),
+    TaskDelete(Option<String>),
+    TaskReopen(Option<String>),
+    TaskArchive(Option<ArchivingOption>),
+    Repair(Option<RepairingOption>),
+    Undo,
+    Daemon,
+    Tui,
+    Status(Option<StatusOption>)
+}
+
+impl Command {
+    /// Can `undo` bring back the state before this command ? Starting
+    /// and stopping pomodori cannot be undone: the timer would not
+    /// match the task file anymore.
+    pub fn can_be_undone(&self) -> bool {
+        match *self {
+            Command::TaskNew(_) | Command::TaskDone(_) | Command::TaskModify(_) |
+            Command::Interrupt(_) | Command::TaskPlan(_) | Command::TaskComment(_) |
+            Command::TaskDelete(_) | Command::TaskReopen(_) | Command::TaskArchive(_) => true,
+            _ => false
+        }
+    }
 }
 
 
+/// Which tasks should be listed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ListingView {
+    /// The "To Do Today" sheet
+    Today,
+    /// Unfinished tasks not on today's sheet
+    Inventory,
+    /// Every unfinished task
+    Current,
+    All
+}
+
 #[derive(Debug)]
 pub struct ListingOption {
-    pub only_current: bool
+    pub view: ListingView,
+    pub format: OutputFormat,
+    pub tags: Vec<String>,
+    pub project: Option<String>,
+    pub state: Option<TaskState>,
+    pub started_since: Option<String>,
+    pub started_until: Option<String>,
+    pub ended_since: Option<String>,
+    pub ended_until: Option<String>,
+    pub search: Option<String>,
+    pub sort: SortKey,
+    pub reverse: bool
+}
+
+#[derive(Debug)]
+pub struct StatusOption {
+    pub format: OutputFormat,
+    /// Display a single line, for status bars
+    pub short: bool,
+    /// Template of the single line, instead of the configured one
+    pub template: Option<String>
+}
+
+#[derive(Debug)]
+pub struct PlanningOption {
+    pub identifiers: Vec<String>,
+    /// Day to plan for, today if not given
+    pub day: Option<String>,
+    /// Put the tasks back to the inventory
+    pub unplan: bool
+}
+
+#[derive(Debug)]
+pub struct LogOption {
+    pub since: Option<String>,
+    pub until: Option<String>,
+    pub task: Option<String>
+}
+
+#[derive(Debug)]
+pub struct StatsOption {
+    pub since: Option<String>,
+    pub until: Option<String>
+}
+
+#[derive(Debug)]
+pub struct CommentOption {
+    pub identifier: String,
+    pub text: Option<String>,
+    /// Number of the note to change
+    pub replace: Option<usize>,
+    /// Number of the note to remove
+    pub remove: Option<usize>,
+    /// Open the notes in $EDITOR
+    pub edit: bool
+}
+
+#[derive(Debug)]
+pub struct ShowingOption {
+    pub identifier: String,
+    pub format: OutputFormat
+}
+
+#[derive(Debug)]
+pub struct ArchivingOption {
+    /// Only archive tasks finished longer ago, e.g. "30d"
+    pub older_than: Option<String>
+}
+
+#[derive(Debug)]
+pub struct RepairingOption {
+    /// Count the pomodoro left behind instead of voiding it
+    pub count: bool
+}
+
+/// Per invocation overrides of the durations set in the config file.
+#[derive(Debug)]
+pub struct StartingOption {
+    pub work_duration: Option<u16>,
+    pub break_duration: Option<u16>,
+    pub long_break_duration: Option<u16>
 }
 
 impl FromStr for Command {
@@ -28,10 +153,28 @@ impl FromStr for Command {
         return match src {
             "start" => Ok(Command::TaskStart(None)),
             "new" => Ok(Command::TaskNew(None)),
-            "list" => Ok(Command::TaskList(None)),
+            "jot" => Ok(Command::TaskJot),
+            "list" | "today" => Ok(Command::TaskList(None)),
+            "plan" => Ok(Command::TaskPlan(None)),
+            "comment" => Ok(Command::TaskComment(None)),
+            "notes" => Ok(Command::TaskNotes(None)),
+            "show" => Ok(Command::TaskShow(None)),
             "done" => Ok(Command::TaskDone(None)),
-            "status" => Ok(Command::Status),
+            "status" => Ok(Command::Status(None)),
             "modify" => Ok(Command::TaskModify(None)),
+            "stop" | "void" => Ok(Command::TaskStop),
+            "pause" => Ok(Command::TaskPause),
+            "resume" => Ok(Command::TaskResume),
+            "interrupt" => Ok(Command::Interrupt(None)),
+            "log" => Ok(Command::Log(None)),
+            "stats" => Ok(Command::Stats(None)),
+            "delete" => Ok(Command::TaskDelete(None)),
+            "reopen" => Ok(Command::TaskReopen(None)),
+            "archive" => Ok(Command::TaskArchive(None)),
+            "repair" => Ok(Command::Repair(None)),
+            "undo" => Ok(Command::Undo),
+            "daemon" => Ok(Command::Daemon),
+            "tui" => Ok(Command::Tui),
             _ => Err(())
         }
     }
@@ -41,7 +184,9 @@ impl FromStr for Command {
 fn new_task(args: Vec<String>) -> Command {
     let mut description = "".to_string();
     let mut pomodori_estimate = 0;
-    let mut kind = None;
+    let mut tags: Vec<String> = vec!();
+    let mut project: Option<String> = None;
+    let mut unplanned = false;
     {
         let mut ap = ArgumentParser::new();
         ap.set_description("Add a new task");
@@ -51,26 +196,56 @@ fn new_task(args: Vec<String>) -> Command {
         ap.refer(&mut pomodori_estimate).add_option(
             &["-e", "--estimated"], Store,
             "Number of pomodori you think this task will take");
-        ap.refer(&mut kind).add_option(
-            &["--type"], StoreOption,
-            "The general category of this task if any");
+        ap.refer(&mut tags).add_option(
+            &["-t", "--tag", "--type"], Collect,
+            "A tag telling what kind of task it is; can be given several times");
+        ap.refer(&mut project).add_option(
+            &["-p", "--project"], StoreOption,
+            "Project of the task, sub-projects separated by slashes: work/clientA/api");
+        ap.refer(&mut unplanned).add_option(
+            &["-u", "--unplanned"], StoreTrue,
+            "Something urgent that came up: put it on today's sheet");
         parse_or_usage(&ap, ap.parse(args, &mut stdout(), &mut stderr()));
     }
-    let t = Task::new(&description, pomodori_estimate, kind);
+    let mut t = Task::new(&description, pomodori_estimate, tags);
+    if let Some(p) = project {
+        t.set_project(&p);
+    }
+    if unplanned {
+        t.mark_as_unplanned();
+    }
+    Command::TaskNew(Some(t))
+}
+
+/// Shortcut to quickly note an unplanned task during a pomodoro.
+fn jot(args: Vec<String>) -> Command {
+    let mut description = "".to_string();
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Jot down an unplanned and urgent task");
+        ap.refer(&mut description).required().add_argument(
+            "description", Store,
+            "Short description of the task");
+        parse_or_usage(&ap, ap.parse(args, &mut stdout(), &mut stderr()));
+    }
+    let mut t = Task::new(&description, 0, vec!());
+    t.mark_as_unplanned();
     Command::TaskNew(Some(t))
 }
 
 fn modify(args: Vec<String>) -> Command {
     let mut uuid_begin = "".to_string();
     let mut modify_option = TaskModification { description: None,
-                                               pomodoro_estimation: None, 
-                                               kind: None };
+                                               pomodoro_estimation: None,
+                                               tags_added: vec!(),
+                                               tags_removed: vec!(),
+                                               project: None };
     {
         let mut ap = ArgumentParser::new();
         ap.set_description("Acts upon an identified command");
         ap.refer(&mut uuid_begin).required().add_argument(
             "identifier", Store,
-            "Enough UUID to identify the task - beware, \
+            "Short id or beginning of the UUID of the task - beware, \
             this can modify even tasks that are done");
         ap.refer(&mut modify_option.description).add_option(
             &["-d", "--description"], StoreOption,
@@ -78,15 +253,195 @@ fn modify(args: Vec<String>) -> Command {
         ap.refer(&mut modify_option.pomodoro_estimation).add_option(
             &["-e", "--estimated"], StoreOption,
             "New estimation of pomodori needed for the task");
-        ap.refer(&mut modify_option.kind).add_option(
-            &["-k", "--kind"], StoreOption,
-            "New type for the task");
+        ap.refer(&mut modify_option.tags_added).add_option(
+            &["-t", "--tag", "-k", "--kind"], Collect,
+            "Tag to add to the task; can be given several times");
+        ap.refer(&mut modify_option.tags_removed).add_option(
+            &["--untag"], Collect,
+            "Tag to remove from the task; can be given several times");
+        ap.refer(&mut modify_option.project).add_option(
+            &["-p", "--project"], StoreOption,
+            "New project for the task, \"\" to remove it from its project");
         parse_or_usage(&ap, ap.parse(args, &mut stdout(), &mut stderr()));
     }
     Command::TaskModify(Some((uuid_begin, modify_option)))
 }
 
 
+fn start(args: Vec<String>) -> Command {
+    let mut uuid_begin = "".to_string();
+    let mut starting_option = StartingOption { work_duration: None,
+                                               break_duration: None,
+                                               long_break_duration: None };
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Start a pomodoro on a task");
+        ap.refer(&mut uuid_begin).required().add_argument(
+            "identifier", Store,
+            "Short id or beginning of the UUID of the task");
+        ap.refer(&mut starting_option.work_duration).add_option(
+            &["-w", "--work"], StoreOption,
+            "Length of the pomodoro, in minutes");
+        ap.refer(&mut starting_option.break_duration).add_option(
+            &["-b", "--break"], StoreOption,
+            "Length of the short pause following the pomodoro, in minutes");
+        ap.refer(&mut starting_option.long_break_duration).add_option(
+            &["-l", "--long-break"], StoreOption,
+            "Length of the long pause ending a cycle, in minutes");
+        parse_or_usage(&ap, ap.parse(args, &mut stdout(), &mut stderr()));
+    }
+    Command::TaskStart(Some((uuid_begin, starting_option)))
+}
+
+fn interrupt(args: Vec<String>) -> Command {
+    let mut kind = InterruptionKind::Internal;
+    let mut note = "".to_string();
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Note an interruption during the current pomodoro");
+        ap.refer(&mut kind).add_option(
+            &["-x", "--external"], StoreConst(InterruptionKind::External),
+            "Someone or something else interrupted you");
+        ap.refer(&mut note).add_argument(
+            "note", Store,
+            "What interrupted you");
+        parse_or_usage(&ap, ap.parse(args, &mut stdout(), &mut stderr()));
+    }
+    Command::Interrupt(Some(Interruption::new(kind, &note)))
+}
+
+fn log(args: Vec<String>) -> Command {
+    let mut log_option = LogOption { since: None, until: None, task: None };
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Lists the pomodori done");
+        ap.refer(&mut log_option.since).add_option(
+            &["-s", "--since"], StoreOption,
+            "First day to display (YYYY-MM-DD)");
+        ap.refer(&mut log_option.until).add_option(
+            &["-u", "--until"], StoreOption,
+            "Last day to display (YYYY-MM-DD)");
+        ap.refer(&mut log_option.task).add_option(
+            &["-t", "--task"], StoreOption,
+            "Only display pomodori done on this task");
+        parse_or_usage(&ap, ap.parse(args, &mut stdout(), &mut stderr()));
+    }
+    Command::Log(Some(log_option))
+}
+
+fn stats(args: Vec<String>) -> Command {
+    let mut stats_option = StatsOption { since: None, until: None };
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Statistics on pomodori and estimates");
+        ap.refer(&mut stats_option.since).add_option(
+            &["-s", "--since"], StoreOption,
+            "First day to consider (YYYY-MM-DD)");
+        ap.refer(&mut stats_option.until).add_option(
+            &["-u", "--until"], StoreOption,
+            "Last day to consider (YYYY-MM-DD)");
+        parse_or_usage(&ap, ap.parse(args, &mut stdout(), &mut stderr()));
+    }
+    Command::Stats(Some(stats_option))
+}
+
+fn comment(args: Vec<String>) -> Command {
+    let mut comment_option = CommentOption { identifier: "".to_string(),
+                                             text: None,
+                                             replace: None,
+                                             remove: None,
+                                             edit: false };
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Add, change or remove notes on a task");
+        ap.refer(&mut comment_option.identifier).required().add_argument(
+            "identifier", Store,
+            "Short id or beginning of the UUID of the task");
+        ap.refer(&mut comment_option.text).add_argument(
+            "text", StoreOption,
+            "Text of the note");
+        ap.refer(&mut comment_option.replace).add_option(
+            &["-r", "--replace"], StoreOption,
+            "Number of the note to replace with the text");
+        ap.refer(&mut comment_option.remove).add_option(
+            &["-d", "--delete"], StoreOption,
+            "Number of the note to remove");
+        ap.refer(&mut comment_option.edit).add_option(
+            &["-e", "--edit"], StoreTrue,
+            "Edit every note of the task in $EDITOR");
+        parse_or_usage(&ap, ap.parse(args, &mut stdout(), &mut stderr()));
+    }
+    Command::TaskComment(Some(comment_option))
+}
+
+fn show(args: Vec<String>) -> Command {
+    let mut showing_option = ShowingOption { identifier: "".to_string(),
+                                             format: OutputFormat::Plain };
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Display everything about a task");
+        ap.refer(&mut showing_option.identifier).required().add_argument(
+            "identifier", Store,
+            "Short id or beginning of the UUID of the task");
+        ap.refer(&mut showing_option.format)
+            .add_option(&["-f", "--format"], Store,
+                        "Output format: plain, json or tsv")
+            .add_option(&["-j", "--json"], StoreConst(OutputFormat::Json),
+                        "Output the task as JSON");
+        parse_or_usage(&ap, ap.parse(args, &mut stdout(), &mut stderr()));
+    }
+    Command::TaskShow(Some(showing_option))
+}
+
+fn status(args: Vec<String>) -> Command {
+    let mut status_option = StatusOption { format: OutputFormat::Plain,
+                                           short: false,
+                                           template: None };
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Display the current pomodoro or pause");
+        ap.refer(&mut status_option.format).add_option(
+            &["-f", "--format"], Store,
+            "Output format: plain, json or tsv");
+        ap.refer(&mut status_option.short).add_option(
+            &["-s", "--short"], StoreTrue,
+            "Display a single line, suitable for status bars");
+        ap.refer(&mut status_option.template).add_option(
+            &["-t", "--template"], StoreOption,
+            "Template of the single line, e.g. \"{icon} {task} {remaining:mm:ss}\"");
+        parse_or_usage(&ap, ap.parse(args, &mut stdout(), &mut stderr()));
+    }
+    Command::Status(Some(status_option))
+}
+
+fn archive(args: Vec<String>) -> Command {
+    let mut archiving_option = ArchivingOption { older_than: None };
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Move finished tasks to the archive, where stats still find them");
+        ap.refer(&mut archiving_option.older_than).add_option(
+            &["-o", "--older-than"], StoreOption,
+            "Only archive tasks finished longer ago than this, e.g. 30d or 4w");
+        parse_or_usage(&ap, ap.parse(args, &mut stdout(), &mut stderr()));
+    }
+    Command::TaskArchive(Some(archiving_option))
+}
+
+fn repair(args: Vec<String>) -> Command {
+    let mut repairing_option = RepairingOption { count: false };
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Clear a pomodoro whose timer is gone (crash, reboot...)");
+        ap.refer(&mut repairing_option.count)
+            .add_option(&["-c", "--count"], StoreTrue,
+                        "Count the pomodoro as done")
+            .add_option(&["-v", "--void"], StoreConst(false),
+                        "Count the pomodoro as interrupted (default)");
+        parse_or_usage(&ap, ap.parse(args, &mut stdout(), &mut stderr()));
+    }
+    Command::Repair(Some(repairing_option))
+}
+
 fn identify(args: Vec<String>) -> Option<String> {
     let mut uuid_begin = "".to_string();
     {
@@ -94,24 +449,93 @@ fn identify(args: Vec<String>) -> Option<String> {
         ap.set_description("Acts upon an identified command");
         ap.refer(&mut uuid_begin).required().add_argument(
             "identifier", Store,
-            r#"Beginning of the UUID of the task"#);
+            "Short id or beginning of the UUID of the task");
         parse_or_usage(&ap, ap.parse(args, &mut stdout(), &mut stderr()));
     }
     Some(uuid_begin)
 }
 
 fn list_task(args: Vec<String>) -> Command {
-    let mut listing_option = ListingOption { only_current: true };
+    let mut listing_option = ListingOption { view: ListingView::Today,
+                                             format: OutputFormat::Plain,
+                                             tags: vec!(),
+                                             project: None,
+                                             state: None,
+                                             started_since: None,
+                                             started_until: None,
+                                             ended_since: None,
+                                             ended_until: None,
+                                             search: None,
+                                             sort: SortKey::Creation,
+                                             reverse: false };
     {
         let mut ap = ArgumentParser::new();
-        ap.set_description("Lists all registered tasks");
-        ap.refer(&mut listing_option.only_current).add_option(&["-a", "--all"], StoreFalse,
-            "Display every tasks, even those who are done");
+        ap.set_description("Lists the tasks to do today");
+        ap.refer(&mut listing_option.view)
+            .add_option(&["-i", "--inventory"], StoreConst(ListingView::Inventory),
+                        "Display the activity inventory: tasks not planned for today")
+            .add_option(&["-c", "--current"], StoreConst(ListingView::Current),
+                        "Display every task that is not done")
+            .add_option(&["-a", "--all"], StoreConst(ListingView::All),
+                        "Display every tasks, even those who are done");
+        ap.refer(&mut listing_option.format).add_option(
+            &["-f", "--format"], Store,
+            "Output format: plain, json or tsv");
+        ap.refer(&mut listing_option.tags).add_option(
+            &["-t", "--tag", "-k", "--kind"], Collect,
+            "Only display tasks with this tag; can be given several times");
+        ap.refer(&mut listing_option.project).add_option(
+            &["-p", "--project"], StoreOption,
+            "Only display tasks of this project, or of its sub-projects");
+        ap.refer(&mut listing_option.state).add_option(
+            &["--state"], StoreOption,
+            "Only display tasks in this state: open, ongoing or done");
+        ap.refer(&mut listing_option.started_since).add_option(
+            &["--started-since"], StoreOption,
+            "Only display tasks started on this day (YYYY-MM-DD) or after");
+        ap.refer(&mut listing_option.started_until).add_option(
+            &["--started-until"], StoreOption,
+            "Only display tasks started on this day (YYYY-MM-DD) or before");
+        ap.refer(&mut listing_option.ended_since).add_option(
+            &["--ended-since"], StoreOption,
+            "Only display tasks done on this day (YYYY-MM-DD) or after");
+        ap.refer(&mut listing_option.ended_until).add_option(
+            &["--ended-until"], StoreOption,
+            "Only display tasks done on this day (YYYY-MM-DD) or before");
+        ap.refer(&mut listing_option.search).add_option(
+            &["-g", "--grep"], StoreOption,
+            "Only display tasks whose description contains this text");
+        ap.refer(&mut listing_option.sort).add_option(
+            &["--sort"], Store,
+            "Sort by creation (default), pomodori, estimate or overrun; \
+             the biggest values come first");
+        ap.refer(&mut listing_option.reverse).add_option(
+            &["-r", "--reverse"], StoreTrue,
+            "Reverse the order of the listing");
         parse_or_usage(&ap, ap.parse(args, &mut stdout(), &mut stderr()));
     }
     Command::TaskList(Some(listing_option))
 }
 
+fn plan(args: Vec<String>) -> Command {
+    let mut planning_option = PlanningOption { identifiers: vec!(), day: None, unplan: false };
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Put tasks on the \"To Do Today\" sheet");
+        ap.refer(&mut planning_option.identifiers).required().add_argument(
+            "identifiers", List,
+            "Short ids or beginnings of the UUID of the tasks");
+        ap.refer(&mut planning_option.day).add_option(
+            &["-d", "--day"], StoreOption,
+            "Plan for another day (YYYY-MM-DD)");
+        ap.refer(&mut planning_option.unplan).add_option(
+            &["-u", "--unplan"], StoreTrue,
+            "Put the tasks back in the activity inventory");
+        parse_or_usage(&ap, ap.parse(args, &mut stdout(), &mut stderr()));
+    }
+    Command::TaskPlan(Some(planning_option))
+}
+
 pub fn parse_or_usage(parser: &ArgumentParser, res: Result<(), i32>) {
     match res {
         Ok(()) => (),
@@ -124,14 +548,14 @@ pub fn parse_or_usage(parser: &ArgumentParser, res: Result<(), i32>) {
 }
 
 pub fn parse() -> Command {
-    let mut subcommand = Command::Status;
+    let mut subcommand = Command::Status(None);
     let mut args = vec!();
     {
         let mut ap = ArgumentParser::new();
         ap.set_description("Pomodoro technique utility");
         ap.refer(&mut subcommand).required()
             .add_argument("command", Store,
-                          r#"Command to run ("start", "new", "list", "done")"#);
+                          r#"Command to run ("start", "stop", "pause", "resume", "interrupt", "new", "jot", "plan", "list", "today", "done", "modify", "comment", "notes", "show", "reopen", "delete", "archive", "repair", "undo", "status", "log", "stats", "daemon", "tui")"#);
         ap.refer(&mut args).
             add_argument("arguments", List, r#"Arguments for command"#);
         ap.stop_on_first_argument(true);
@@ -139,11 +563,44 @@ pub fn parse() -> Command {
     }
     args.insert(0, format!("subcommand {:?}", subcommand));
     match subcommand {
-        Command::TaskStart(_) => Command::TaskStart(identify(args)),
+        Command::TaskStart(_) => start(args),
         Command::TaskNew(_) => new_task(args),
+        Command::TaskJot => jot(args),
         Command::TaskDone(_) => Command::TaskDone(identify(args)),
         Command::TaskList(_) => list_task(args),
         Command::TaskModify(_) => modify(args),
+        Command::Interrupt(_) => interrupt(args),
+        Command::Log(_) => log(args),
+        Command::Stats(_) => stats(args),
+        Command::TaskPlan(_) => plan(args),
+        Command::TaskComment(_) => comment(args),
+        Command::TaskNotes(_) => Command::TaskNotes(identify(args)),
+        Command::TaskShow(_) => show(args),
+        Command::TaskDelete(_) => Command::TaskDelete(identify(args)),
+        Command::TaskReopen(_) => Command::TaskReopen(identify(args)),
+        Command::TaskArchive(_) => archive(args),
+        Command::Repair(_) => repair(args),
+        Command::Status(_) => status(args),
         _ => subcommand
     }
 }
+
+#[test]
+fn test_parse_tags() {
+    let args = |x: &[&str]| x.iter().map(|x| x.to_string()).collect::<Vec<String>>();
+    match new_task(args(&["new", "-t", "a", "Fix the bug", "--tag", "b"])) {
+        Command::TaskNew(Some(t)) => {
+            assert_eq!(t.description, "Fix the bug");
+            assert_eq!(t.tags(), ["a", "b"]);
+        },
+        c => panic!("unexpected command {:?}", c)
+    }
+    match modify(args(&["modify", "-k", "a", "12", "--untag", "b"])) {
+        Command::TaskModify(Some((id, m))) => {
+            assert_eq!(id, "12");
+            assert_eq!(m.tags_added, ["a"]);
+            assert_eq!(m.tags_removed, ["b"]);
+        },
+        c => panic!("unexpected command {:?}", c)
+    }
+}
@@ -1,84 +1,377 @@
-use std::fs::File;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
 use std::io::Read;
-use std::io::Error;
 use std::io::Write;
+use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
 use ini::Ini;
+use libc;
 use xdg;
 use pomorust::model::Task;
 use pomorust::model::Context;
+use pomorust::model::Durations;
+use pomorust::model::Recovery;
+use pomorust::error::Error;
+use pomorust::history::{LogEntry, Outcome};
+use pomorust::output::DEFAULT_STATUS_TEMPLATE;
+use pomorust::record::{Record, parse_records, serialize_record};
 
 const CONF_FILE_NAME: &'static str = ".pomorust.ini";
 const TASK_FILE_NAME: &'static str = "task";
+/// Files are written to their name with this suffix first, then renamed
+/// over the real one.
+const TMP_SUFFIX: &'static str = ".tmp";
+const LOCK_FILE_NAME: &'static str = "task.lock";
+/// Append-only log of every pomodoro done.
+const HISTORY_FILE_NAME: &'static str = "history";
+/// Finished tasks taken out of the task file, in the same format
+const ARCHIVE_FILE_NAME: &'static str = "archive";
+/// What a daemon started in the background has to say goes there.
+const DAEMON_LOG_FILE_NAME: &'static str = "daemon.log";
+/// Files restored by `undo`, their copy being suffixed with `UNDO_SUFFIX`
+const UNDOABLE_FILES: [&'static str; 2] = [TASK_FILE_NAME, ARCHIVE_FILE_NAME];
+const UNDO_SUFFIX: &'static str = ".undo";
+/// First field of the task file, followed by the version of its format.
+/// Files without it are from before versioning (version 1).
+const TASK_FILE_HEADER: &'static str = "pomorust";
+/// Version 3 appended fields to tasks and to the context, and moved
+/// the kind and the comment of tasks to the tags and the notes: older
+/// versions of pomorust would lose them.
+const TASK_FILE_VERSION: u16 = 3;
 
 /// Technically, we should put the config file in XDG_CONFIG_HOME.
 /// But I find this a bit bothersome for a few lines.
 /// So we will put everything in XDG_DATA_HOME.
-fn get_path_for(path: &Path) -> PathBuf {
-    let xdg_dirs = xdg::BaseDirectories::with_prefix("pomorust").unwrap();
-    xdg_dirs.place_data_file(path).expect("Could not create rust in local config directory")
+fn get_path_for(path: &Path) -> Result<PathBuf, Error> {
+    let xdg_dirs = try!(xdg::BaseDirectories::with_prefix("pomorust")
+        .map_err(|_| Error::Config("could not find XDG directories".to_string())));
+    xdg_dirs.place_data_file(path)
+        .map_err(|_| Error::Config("could not create pomorust data directory".to_string()))
 }
 
-pub fn create_context() -> Context {
-    let ini = read_ini_file();
+/// Sockets go in XDG_RUNTIME_DIR, which only lives as long as the session.
+pub fn get_runtime_path_for(path: &str) -> Result<PathBuf, Error> {
+    let xdg_dirs = try!(xdg::BaseDirectories::with_prefix("pomorust")
+        .map_err(|_| Error::Config("could not find XDG directories".to_string())));
+    xdg_dirs.place_runtime_file(path)
+        .map_err(|_| Error::Config("could not create pomorust runtime directory \
+                                    (is XDG_RUNTIME_DIR set ?)".to_string()))
+}
+
+pub fn create_context() -> Result<Context, Error> {
+    let ini = try!(read_ini_file());
     let main_sec = ini.general_section();
-    let ref with_sound = main_sec["use_sound"];
-    let ref with_notification = main_sec["use_notification"];
-    let mut context = read_task_file();
+    let with_sound = main_sec.get("use_sound").map_or("true", |x| x);
+    let with_notification = main_sec.get("use_notification").map_or("true", |x| x);
+    // Durations were added later on: older config files might not have them.
+    let defaults = Durations::default();
+    let duration_or = |key: &str, default: u16| -> u16 {
+        main_sec.get(key).and_then(|x| x.parse().ok()).unwrap_or(default)
+    };
+    let mut context = try!(read_task_file());
     context.use_sound = with_sound == "true";
     context.use_notification = with_notification == "true";
-    context
+    context.durations = Durations {
+        work: duration_or("pomodoro_duration", defaults.work),
+        short_break: duration_or("short_break_duration", defaults.short_break),
+        long_break: duration_or("long_break_duration", defaults.long_break),
+        long_break_interval: duration_or("long_break_interval", defaults.long_break_interval)
+    };
+    context.max_pause = duration_or("max_pause_duration", 0);
+    if let Some(template) = main_sec.get("status_template") {
+        context.status_template = template.to_string();
+    }
+    context.stale_timer = main_sec.get("stale_timer").and_then(|x| x.parse().ok())
+        .unwrap_or(Recovery::Void);
+    Ok(context)
 }
 
-pub fn read_ini_file() -> Ini {
-    match Ini::load_from_file(CONF_FILE_NAME) {
-        Ok(i) => i,
-        Err(_) => create_ini_file()
+/// Clear the timer, counting or voiding the pomodoro being done.
+/// Return what became of the pomodoro, if there was one.
+pub fn repair_timer(context: &mut Context, count: bool) -> Result<Option<Outcome>, Error> {
+    let entry = context.recover(count);
+    if let Some(ref e) = entry {
+        try!(append_history(e));
     }
+    try!(write_timer_change(context));
+    Ok(entry.map(|x| x.outcome))
 }
 
-pub fn create_ini_file() -> Ini {
+pub fn read_ini_file() -> Result<Ini, Error> {
+    let path = try!(get_path_for(Path::new(CONF_FILE_NAME)));
+    if !path.exists() {
+        return create_ini_file();
+    }
+    Ini::load_from_file(try!(path.to_str()
+        .ok_or(Error::Config("invalid config file path".to_string()))))
+        .map_err(|_| Error::Config("could not read the config file".to_string()))
+}
+
+pub fn create_ini_file() -> Result<Ini, Error> {
+    let defaults = Durations::default();
     let mut conf = Ini::new();
     conf.with_section(None::<String>)
         .set("use_notification", "true")
-        .set("use_sound", "true");
-    conf.write_to_file(get_path_for(Path::new(CONF_FILE_NAME)).to_str().expect("Invalid path")).unwrap();
-    conf
+        .set("use_sound", "true")
+        .set("pomodoro_duration", defaults.work.to_string())
+        .set("short_break_duration", defaults.short_break.to_string())
+        .set("long_break_duration", defaults.long_break.to_string())
+        .set("long_break_interval", defaults.long_break_interval.to_string())
+        .set("status_template", DEFAULT_STATUS_TEMPLATE)
+        .set("max_pause_duration", "0")
+        .set("stale_timer", "void");
+    let path = try!(get_path_for(Path::new(CONF_FILE_NAME)));
+    try!(conf.write_to_file(try!(path.to_str()
+        .ok_or(Error::Config("invalid config file path".to_string())))));
+    Ok(conf)
 }
 
-pub fn read_task_file() -> Context {
-    let mut file = match File::open(&get_path_for(Path::new(TASK_FILE_NAME))) {
+pub fn read_task_file() -> Result<Context, Error> {
+    let mut file = match File::open(&try!(get_path_for(Path::new(TASK_FILE_NAME)))) {
         Ok(file) => file,
-        Err(_) => return Context::default()
+        Err(_) => return Ok(Context::default())
     };
     let mut file_txt = String::new();
-    match file.read_to_string(&mut file_txt) {
-        Ok(_) => {},
-        Err(_) => return Context::default()
+    try!(file.read_to_string(&mut file_txt));
+
+    let is_legacy = !file_txt.starts_with(TASK_FILE_HEADER);
+    let records = if is_legacy {
+        parse_legacy_records(&file_txt)
+    } else {
+        let mut records = parse_records(&file_txt);
+        let version: u16 = try!(records[0].1.get(1).and_then(|x| x.parse().ok())
+            .ok_or(Error::Parse(1, "version not parsable.".to_string())));
+        if version > TASK_FILE_VERSION {
+            return Err(Error::Parse(1, "file was written by a more recent version \
+                                        of pomorust.".to_string()));
+        }
+        records.remove(0);
+        records
     };
+    if records.is_empty() {
+        return Ok(Context::default());
+    }
 
-    let lines = file_txt.split("\n").collect::<Vec<&str>>();
-    let context_line = lines[0];
-    let mut context = Context::from_csv_line(&context_line);
+    let (context_line, ref context_fields) = records[0];
+    let mut context = try!(Context::from_record(context_fields)
+        .map_err(|e| Error::Parse(context_line, e)));
+    for &(line, ref fields) in records.iter().skip(1) {
+        context.tasks.push(try!(Task::from_record(fields).map_err(|e| Error::Parse(line, e))));
+    }
+    context.number_tasks();
+    // Migrate the file at once, so that the old format never gets read again.
+    if is_legacy {
+        try!(write_task_file(&context));
+    }
+    Ok(context)
+}
 
-    let mut tasks: Vec<Task> = Vec::new();
-    for l in lines.into_iter().skip(1) {
-        if l.len() > 0 {
-            tasks.push(Task::from_csv(l));
-        }
+/// Advisory lock on the task file, released when dropped.
+/// Every read-modify-write cycle on the task file should hold it,
+/// so that the background pomodoro process and the commands run
+/// by the user do not overwrite each other's changes.
+pub struct TaskFileLock {
+    file: File
+}
+
+impl Drop for TaskFileLock {
+    fn drop(&mut self) {
+        unsafe { libc::flock(self.file.as_raw_fd(), libc::LOCK_UN); }
+    }
+}
+
+/// Wait until no other pomorust process holds the lock, then take it.
+pub fn lock_task_file() -> Result<TaskFileLock, Error> {
+    let file = try!(OpenOptions::new().write(true).create(true)
+        .open(try!(get_path_for(Path::new(LOCK_FILE_NAME)))));
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(Error::Io(io::Error::last_os_error()));
     }
-    context.tasks = tasks;
-    context
+    Ok(TaskFileLock { file: file })
+}
+
+/// Write to a temporary file, then rename it over the real one:
+/// a crash while writing never leaves a truncated file.
+fn replace_file(name: &str, content: &[u8]) -> Result<(), Error> {
+    let tmp_path = try!(get_path_for(Path::new(&format!("{}{}", name, TMP_SUFFIX))));
+    let mut file = try!(File::create(&tmp_path));
+    try!(file.write_all(content));
+    try!(file.sync_all());
+    try!(fs::rename(&tmp_path, try!(get_path_for(Path::new(name)))));
+    Ok(())
 }
 
 pub fn write_task_file(context: &Context) -> Result<(), Error> {
-    let tasks_as_strings = context.tasks.iter().map(|x| x.to_csv()).collect::<Vec<String>>();
-    let mut file = File::create(&get_path_for(Path::new(TASK_FILE_NAME))).unwrap();
+    let mut content = serialize_record(&vec!(TASK_FILE_HEADER.to_string(),
+                                             TASK_FILE_VERSION.to_string()));
+    content.push_str(&serialize_record(&context.metadata_to_record()));
+    for t in context.tasks.iter() {
+        content.push_str(&serialize_record(&t.to_record()));
+    }
+    replace_file(TASK_FILE_NAME, content.as_bytes())
+}
 
-    try!(file.write(&context.metadata_to_csv_line().into_bytes()));
-    for s in tasks_as_strings {
-        try!(file.write(&s.into_bytes()));
+/// Starting, ending or voiding a pomodoro cannot be undone: the
+/// undo point would bring back a timer that does not run anymore.
+pub fn write_timer_change(context: &Context) -> Result<(), Error> {
+    try!(forget_undo_point());
+    write_task_file(context)
+}
+
+pub fn append_history(entry: &LogEntry) -> Result<(), Error> {
+    let mut file = try!(OpenOptions::new().append(true).create(true)
+        .open(try!(get_path_for(Path::new(HISTORY_FILE_NAME)))));
+    try!(file.write(&serialize_record(&entry.to_record()).into_bytes()));
+    try!(file.sync_all());
+    Ok(())
+}
+
+pub fn read_history() -> Result<Vec<LogEntry>, Error> {
+    let mut file = match File::open(&try!(get_path_for(Path::new(HISTORY_FILE_NAME)))) {
+        Ok(file) => file,
+        Err(_) => return Ok(vec!())
+    };
+    let mut file_txt = String::new();
+    try!(file.read_to_string(&mut file_txt));
+    let mut entries = vec!();
+    for (line, fields) in parse_records(&file_txt) {
+        entries.push(try!(LogEntry::from_record(&fields).map_err(|e| Error::Parse(line, e))));
+    }
+    Ok(entries)
+}
+
+pub fn daemon_log_file() -> Result<File, Error> {
+    Ok(try!(OpenOptions::new().append(true).create(true)
+        .open(try!(get_path_for(Path::new(DAEMON_LOG_FILE_NAME))))))
+}
+
+/// Append tasks to the archive, then write the task file they were
+/// taken out of. If the task file cannot be written, the tasks are
+/// taken out of the archive again: they never end up in both files.
+pub fn archive_tasks(context: &Context, tasks: &[Task]) -> Result<(), Error> {
+    let mut file = try!(OpenOptions::new().append(true).create(true)
+        .open(try!(get_path_for(Path::new(ARCHIVE_FILE_NAME)))));
+    let archive_length = try!(file.metadata()).len();
+    for t in tasks.iter() {
+        try!(file.write(&serialize_record(&t.to_record()).into_bytes()));
     }
     try!(file.sync_all());
+    if let Err(e) = write_task_file(context) {
+        try!(file.set_len(archive_length));
+        return Err(e);
+    }
     Ok(())
 }
+
+pub fn read_archive() -> Result<Vec<Task>, Error> {
+    let mut file = match File::open(&try!(get_path_for(Path::new(ARCHIVE_FILE_NAME)))) {
+        Ok(file) => file,
+        Err(_) => return Ok(vec!())
+    };
+    let mut file_txt = String::new();
+    try!(file.read_to_string(&mut file_txt));
+    let mut tasks = vec!();
+    for (line, fields) in parse_records(&file_txt) {
+        tasks.push(try!(Task::from_record(&fields).map_err(|e| Error::Parse(line, e))));
+    }
+    Ok(tasks)
+}
+
+/// Content of the task and archive files before a command; a missing
+/// file is the same as an empty one.
+pub struct UndoPoint {
+    contents: Vec<Vec<u8>>
+}
+
+impl UndoPoint {
+    /// Keep this state for `undo`, once the command succeeded.
+    pub fn save(&self) -> Result<(), Error> {
+        for (name, content) in UNDOABLE_FILES.iter().zip(self.contents.iter()) {
+            try!(replace_file(&format!("{}{}", name, UNDO_SUFFIX), content));
+        }
+        Ok(())
+    }
+}
+
+pub fn undo_point() -> Result<UndoPoint, Error> {
+    let mut contents = vec!();
+    for name in UNDOABLE_FILES.iter() {
+        let mut content = vec!();
+        if let Ok(mut file) = File::open(&try!(get_path_for(Path::new(name)))) {
+            try!(file.read_to_end(&mut content));
+        }
+        contents.push(content);
+    }
+    Ok(UndoPoint { contents: contents })
+}
+
+/// Drop the saved state, once it cannot be brought back safely.
+pub fn forget_undo_point() -> Result<(), Error> {
+    for name in UNDOABLE_FILES.iter() {
+        let path = try!(get_path_for(Path::new(&format!("{}{}", name, UNDO_SUFFIX))));
+        if let Err(e) = fs::remove_file(&path) {
+            if e.kind() != io::ErrorKind::NotFound {
+                return Err(Error::Io(e));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Put the task and archive files back as they were before the last
+/// command that could be undone. There is a single level of undo.
+pub fn undo() -> Result<(), Error> {
+    let task_undo = try!(get_path_for(Path::new(&format!("{}{}", TASK_FILE_NAME, UNDO_SUFFIX))));
+    if !task_undo.exists() {
+        return Err(Error::InvalidCommand("nothing to undo".to_string()));
+    }
+    // The task file goes last: as long as its copy is there, undo
+    // can be tried again.
+    for name in UNDOABLE_FILES.iter().rev() {
+        let path = try!(get_path_for(Path::new(name)));
+        try!(fs::rename(try!(get_path_for(Path::new(&format!("{}{}", name, UNDO_SUFFIX)))),
+                        path));
+    }
+    Ok(())
+}
+
+/// Before versioning, the task file was a list of lines of fields
+/// separated by semicolons, without any escaping.
+fn parse_legacy_records(text: &str) -> Vec<Record> {
+    text.split("\n")
+        .enumerate()
+        .filter(|&(_, x)| x.len() > 0)
+        .map(|(i, x)| (i + 1, x.split(";").map(|f| f.to_string()).collect()))
+        .collect()
+}
+
+#[test]
+fn test_task_round_trip() {
+    let task = Task::new("Fix; the \"parser\"\nand the rest", 3, vec!("a;b".to_string()));
+    let records = parse_records(&serialize_record(&task.to_record()));
+    let parsed = Task::from_record(&records[0].1).unwrap();
+    assert_eq!(parsed.description, task.description);
+    assert_eq!(parsed.tags(), ["a;b"]);
+}
+
+#[test]
+fn test_legacy_records() {
+    let text = ";0;;false\nSome task;5ad3e2a6-1d6a-4a4d-8e7f-1c3a4a6c0e11;1;2;;false;;;\n";
+    let records = parse_legacy_records(text);
+    assert_eq!(records.len(), 2);
+    let task = Task::from_record(&records[1].1).unwrap();
+    assert_eq!(task.description, "Some task");
+    assert_eq!(records[1].0, 2);
+}
+
+#[test]
+fn test_records_match_version() {
+    // Fields of a task and of the context written by each version: records
+    // cannot change without a new `TASK_FILE_VERSION` and a line here.
+    let lengths = [(2, 9, 5), (3, 19, 8)];
+    let &(version, task_length, context_length) = lengths.last().unwrap();
+    assert_eq!(version, TASK_FILE_VERSION);
+    assert_eq!(Task::new("Bump the version", 1, vec!()).to_record().len(), task_length);
+    assert_eq!(Context::default().metadata_to_record().len(), context_length);
+}
@@ -0,0 +1,686 @@
+//! The daemon owns the timer: it starts pomodori, ends them and the
+//! breaks following them, and notifies the user. It is run with
+//! `pomorust daemon`, or spawned by `pomorust start` if none is running.
+//!
+//! It listens on the Unix socket `pomorust/daemon.sock`, under
+//! `$XDG_RUNTIME_DIR`. A client connects, writes a request line, reads
+//! a response line, and the connection is closed. Fields are separated
+//! by spaces. A single daemon runs at a time: it holds a lock on
+//! `daemon.lock`, next to the socket.
+//!
+//! Requests:
+//!
+//! - `START <task> <work> <short_break> <long_break>`: start a pomodoro
+//!   on the task (short id or beginning of the UUID); durations are in
+//!   minutes
+//! - `STOP`: void the pomodoro being done, or end the break
+//! - `PAUSE`: freeze the time left in the pomodoro or break
+//! - `RESUME`
+//! - `STATUS`
+//!
+//! Responses are `OK` followed by fields, or `ERR <code> <message>`,
+//! code being the exit code of the error (see `Error::exit_code`).
+//! Newlines and backslashes in messages are escaped as `\n` and `\\`.
+//!
+//! - `START`: `OK <uuid of the task>`
+//! - `STOP`: `OK idle`, `OK voided` or `OK break_over`
+//! - `PAUSE`: `OK idle`, `OK paused` or `OK already_paused`
+//! - `RESUME`: `OK idle`, `OK resumed` or `OK not_paused`
+//! - `STATUS`: `OK <phase> <started> <length> <paused_seconds> <paused_at>`,
+//!   phase being `idle`, `work`, `short_break` or `long_break`; when the
+//!   pomodoro or break started (RFC 3339) and its length in minutes are
+//!   `-` when idle. `paused_seconds` is the time spent paused before the
+//!   current pause, which started at `paused_at` (`-` if not paused)
+//!
+//! The deadline of the timer is checked against the wall clock, so that
+//! a pomodoro that should have ended while the machine was suspended
+//! ends as soon as it wakes up; the time spent suspended is reported.
+//!
+//! A pomodoro paused for longer than `max_pause_duration` minutes (see
+//! the config file) is voided, and a paused break is ended.
+//!
+//! The daemon keeps the task file up to date, so that commands which
+//! do not talk to it still know about the pomodoro being done. If the
+//! timer of the task file was cleared as stale in the meantime (see
+//! `create_context`), the daemon drops its own.
+use std::fs;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::process;
+use std::thread;
+use std::time;
+use chrono;
+use libc;
+use uuid::Uuid;
+use pomorust::config;
+use pomorust::error::Error;
+use pomorust::history::{LogEntry, Outcome};
+use pomorust::model::{Context, Durations, Recovery};
+use pomorust::utils;
+use pomorust::utils::MaybeLocalDate;
+
+const SOCKET_FILE_NAME: &'static str = "daemon.sock";
+/// Held by the running daemon, for as long as it runs.
+const LOCK_FILE_NAME: &'static str = "daemon.lock";
+/// How long the daemon waits for requests before checking the timer.
+const POLL_INTERVAL_MS: u64 = 200;
+/// How long a freshly spawned daemon has to open its socket.
+const SPAWN_TIMEOUT_MS: u64 = 3000;
+/// How long a daemon has to answer, when asked if it is running.
+const PING_TIMEOUT_MS: u64 = 1000;
+
+#[derive(Debug, PartialEq)]
+pub enum Request {
+    /// Task, then length of the pomodoro, short and long breaks
+    Start(String, u16, u16, u16),
+    Stop,
+    Pause,
+    Resume,
+    Status
+}
+
+impl Request {
+    pub fn to_line(&self) -> String {
+        match *self {
+            Request::Start(ref task, work, short_break, long_break) =>
+                format!("START {} {} {} {}", task, work, short_break, long_break),
+            Request::Stop => "STOP".to_string(),
+            Request::Pause => "PAUSE".to_string(),
+            Request::Resume => "RESUME".to_string(),
+            Request::Status => "STATUS".to_string()
+        }
+    }
+
+    pub fn from_line(line: &str) -> Result<Request, String> {
+        let fields = line.split_whitespace().collect::<Vec<&str>>();
+        let duration = |i: usize| -> Result<u16, String> {
+            fields[i].parse().map_err(|_| format!("invalid duration \"{}\"", fields[i]))
+        };
+        match fields.first() {
+            Some(&"START") if fields.len() == 5 =>
+                Ok(Request::Start(fields[1].to_string(), try!(duration(2)),
+                                  try!(duration(3)), try!(duration(4)))),
+            Some(&"STOP") => Ok(Request::Stop),
+            Some(&"PAUSE") => Ok(Request::Pause),
+            Some(&"RESUME") => Ok(Request::Resume),
+            Some(&"STATUS") => Ok(Request::Status),
+            _ => Err(format!("unknown request \"{}\"", line.trim()))
+        }
+    }
+}
+
+fn escape(message: &str) -> String {
+    message.replace("\\", "\\\\").replace("\n", "\\n")
+}
+
+fn unescape(message: &str) -> String {
+    let mut unescaped = String::with_capacity(message.len());
+    let mut chars = message.chars();
+    while let Some(c) = chars.next() {
+        match (c, c == '\\') {
+            (_, true) => match chars.next() {
+                Some('n') => unescaped.push('\n'),
+                Some(x) => unescaped.push(x),
+                None => unescaped.push('\\')
+            },
+            (c, false) => unescaped.push(c)
+        }
+    }
+    unescaped
+}
+
+/// Fields of an `OK` response, or the error of an `ERR` one.
+fn parse_response(line: &str) -> Result<Vec<String>, Error> {
+    let line = line.trim_right_matches('\n');
+    if line == "OK" || line.starts_with("OK ") {
+        Ok(line[2..].split_whitespace().map(|x| x.to_string()).collect())
+    } else if line.starts_with("ERR ") {
+        let mut parts = line[4..].splitn(2, ' ');
+        let code = parts.next().and_then(|x| x.parse().ok()).unwrap_or(1);
+        Err(Error::Remote(code, unescape(parts.next().unwrap_or(""))))
+    } else {
+        Err(Error::Daemon(format!("unexpected answer \"{}\"", line)))
+    }
+}
+
+fn socket_path() -> Result<PathBuf, Error> {
+    config::get_runtime_path_for(SOCKET_FILE_NAME)
+}
+
+/// Send a request to the daemon, and return the fields of its answer.
+pub fn send(request: &Request) -> Result<Vec<String>, Error> {
+    let mut stream = try!(UnixStream::connect(try!(socket_path()))
+        .map_err(|e| Error::Daemon(format!("no daemon running ({})", e))));
+    // The daemon may die while answering.
+    let lost = |e: io::Error| Error::Daemon(format!("connection lost ({})", e));
+    try!(stream.write_all(format!("{}\n", request.to_line()).as_bytes()).map_err(&lost));
+    let mut answer = String::new();
+    try!(BufReader::new(stream).read_line(&mut answer).map_err(&lost));
+    parse_response(&answer)
+}
+
+/// Does a daemon answer ? One too busy to answer in time (it may be
+/// waiting for the task file lock, held by the caller) still counts.
+fn is_running() -> bool {
+    let mut stream = match socket_path().and_then(|x| Ok(try!(UnixStream::connect(x)))) {
+        Ok(stream) => stream,
+        Err(_) => return false
+    };
+    let sent = stream.set_read_timeout(Some(time::Duration::from_millis(PING_TIMEOUT_MS)))
+        .and_then(|_| stream.write_all(format!("{}\n", Request::Status.to_line()).as_bytes()));
+    if sent.is_err() {
+        return false;
+    }
+    let mut answer = String::new();
+    match BufReader::new(stream).read_line(&mut answer) {
+        Ok(_) => parse_response(&answer).is_ok(),
+        Err(e) => e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut
+    }
+}
+
+/// Read the context, dealing first with a timer left behind by a crash
+/// or a reboot: it would keep any pomodoro from being started.
+pub fn create_context() -> Result<Context, Error> {
+    let mut context = try!(config::create_context());
+    let owner_running = context.timer_pid.map_or(false, utils::is_pomorust_process);
+    // A daemon that answers ends its timer itself, however late: the
+    // machine may just have woken up.
+    if context.has_stale_timer(owner_running) && !is_running() {
+        try!(recover_stale_timer(&mut context));
+    }
+    Ok(context)
+}
+
+fn recover_stale_timer(context: &mut Context) -> Result<(), Error> {
+    let count = match context.stale_timer {
+        Recovery::Ask => {
+            writeln!(&mut io::stderr(), "The timer of the last pomodoro is gone (crash or \
+                                         reboot ?) : run `pomorust repair --count` or \
+                                         `pomorust repair --void`.").unwrap();
+            return Ok(());
+        },
+        r => r == Recovery::Count
+    };
+    let message = match try!(config::repair_timer(context, count)) {
+        Some(Outcome::Completed) => "counted as done",
+        Some(Outcome::Interrupted) => "voided",
+        None => "cleared"
+    };
+    writeln!(&mut io::stderr(), "The timer of the last pomodoro was gone (crash or reboot ?) : \
+                                 {}.", message).unwrap();
+    Ok(())
+}
+
+/// Run a daemon in the background, and wait for it to listen.
+pub fn spawn() -> Result<(), Error> {
+    try!(utils::run_background_process(vec!("daemon".to_string()),
+                                       try!(config::daemon_log_file())));
+    let path = try!(socket_path());
+    let mut waited = 0;
+    while UnixStream::connect(&path).is_err() {
+        if waited >= SPAWN_TIMEOUT_MS {
+            return Err(Error::Daemon("the daemon did not start".to_string()));
+        }
+        thread::sleep(time::Duration::from_millis(POLL_INTERVAL_MS));
+        waited += POLL_INTERVAL_MS;
+    }
+    Ok(())
+}
+
+/// Send a request, starting a daemon first if none is running.
+pub fn send_or_spawn(request: &Request) -> Result<Vec<String>, Error> {
+    match send(request) {
+        Err(Error::Daemon(_)) => {
+            try!(spawn());
+            send(request)
+        },
+        answer => answer
+    }
+}
+
+/// Void the pomodoro, or end the break; return the answer to `STOP`.
+pub fn stop_timer() -> Result<String, Error> {
+    match send(&Request::Stop) {
+        Ok(answer) => Ok(answer.into_iter().next().unwrap_or("idle".to_string())),
+        Err(Error::Daemon(_)) => stop_without_daemon(),
+        Err(e) => Err(e)
+    }
+}
+
+/// Without a daemon, the task file may still tell of a pomodoro
+/// (the daemon was killed, or the timer process of an older version
+/// is running): kill the process running the timer, and void it.
+fn stop_without_daemon() -> Result<String, Error> {
+    let _lock = try!(config::lock_task_file());
+    let mut context = try!(create_context());
+    if context.timer.is_none() && !context.has_ongoing_task() {
+        return Ok("idle".to_string());
+    }
+    // The pid may have been given to another program since.
+    match context.timer_pid {
+        Some(pid) if utils::is_pomorust_process(pid) => try!(utils::terminate_process(pid)),
+        _ => ()
+    }
+    let answer = if context.pause {
+        "break_over"
+    } else {
+        if let Some(task) = context.get_ongoing_task() {
+            let start = context.timer.unwrap_or(chrono::Local::now());
+            try!(config::append_history(&LogEntry::new(start, task.uuid,
+                                                       Outcome::Interrupted, 0)));
+        }
+        "voided"
+    };
+    context.interrupt();
+    try!(config::write_timer_change(&context));
+    Ok(answer.to_string())
+}
+
+/// What to tell the user about an answer of the daemon.
+pub fn answer_message(answer: &str) -> &'static str {
+    match answer {
+        "voided" => "Pomodoro voided.",
+        "break_over" => "Break is over.",
+        "paused" => "Paused.",
+        "already_paused" => "Already paused.",
+        "resumed" => "Resumed.",
+        "not_paused" => "Not paused.",
+        _ => "Idle."
+    }
+}
+
+/// Replace the timer read from the task file by the one of the daemon.
+/// Without a daemon, no timer is running.
+pub fn sync_timer(context: &mut Context) -> Result<(), Error> {
+    let fields = match send(&Request::Status) {
+        Ok(fields) => fields,
+        Err(Error::Daemon(_)) => vec!("idle".to_string()),
+        Err(e) => return Err(e)
+    };
+    let date = |i: usize| -> Result<MaybeLocalDate, Error> {
+        match fields.get(i) {
+            Some(x) if x != "-" => x.parse::<chrono::DateTime<chrono::Local>>().map(|x| Some(x))
+                .map_err(|_| Error::Daemon(format!("invalid date \"{}\"", x))),
+            _ => Ok(None)
+        }
+    };
+    let started = try!(date(1));
+    context.pause = started.is_some() && fields[0] != "work";
+    context.timer = started;
+    context.timer_length = fields.get(2).and_then(|x| x.parse().ok());
+    context.paused_seconds = fields.get(3).and_then(|x| x.parse().ok()).unwrap_or(0);
+    context.paused_at = try!(date(4));
+    Ok(())
+}
+
+/// The timer, as seen by the daemon.
+struct Daemon {
+    /// Task of the pomodoro being done
+    task: Option<Uuid>,
+    /// Start of the pomodoro or break being timed
+    started: MaybeLocalDate,
+    /// Length, in minutes, of the pomodoro or break being timed
+    length: u16,
+    pause: bool,
+    /// Durations requested when the pomodoro was started
+    durations: Durations,
+    /// Since when the timer is paused, if it is
+    paused_at: MaybeLocalDate,
+    /// Seconds spent paused before `paused_at`
+    paused_seconds: i64,
+    /// Minutes after which a pause voids the pomodoro, 0 for never
+    max_pause: u16,
+    /// Time the machine spent suspended during the pomodoro or break
+    suspended: chrono::Duration
+}
+
+impl Daemon {
+    fn new() -> Daemon {
+        Daemon { task: None, started: None, length: 0, pause: false,
+                 durations: Durations::default(), paused_at: None, paused_seconds: 0,
+                 max_pause: 0, suspended: chrono::Duration::zero() }
+    }
+
+    fn reset(&mut self) {
+        *self = Daemon::new();
+    }
+
+    /// Read the context, as clients do; the daemon cannot ask itself if
+    /// it is running, but it ends its own timer, however late.
+    fn context(&self) -> Result<Context, Error> {
+        let mut context = try!(config::create_context());
+        // The daemon may have been given the pid of one that died
+        // before a reboot: its own timer is the one it started.
+        if context.timer.is_none() || context.timer != self.started {
+            let pid = process::id();
+            let owner_running = context.timer_pid
+                .map_or(false, |x| x != pid && utils::is_pomorust_process(x));
+            if context.has_stale_timer(owner_running) {
+                try!(recover_stale_timer(&mut context));
+            }
+        }
+        Ok(context)
+    }
+
+    fn answer(&mut self, request: Request) -> Result<String, Error> {
+        match request {
+            Request::Start(task, work, short_break, long_break) =>
+                self.start(&task, work, short_break, long_break).map(|x| x.to_string()),
+            Request::Stop => self.stop().map(|x| x.to_string()),
+            Request::Pause => self.pause().map(|x| x.to_string()),
+            Request::Resume => self.resume().map(|x| x.to_string()),
+            Request::Status => self.status()
+        }
+    }
+
+    fn start(&mut self, identifier: &str, work: u16, short_break: u16,
+             long_break: u16) -> Result<Uuid, Error> {
+        let _lock = try!(config::lock_task_file());
+        let mut context = try!(self.context());
+        if context.has_ongoing_task() {
+            return Err(Error::TaskOngoing);
+        }
+        let uuid = {
+            let task = try!(context.get_task(identifier));
+            task.before_starting_pomodoro();
+            task.uuid
+        };
+        // Starting a pomodoro during a break ends the break.
+        context.pause = false;
+        context.timer = Some(chrono::Local::now());
+        context.timer_length = Some(work);
+        context.timer_pid = Some(process::id());
+        context.clear_paused();
+        try!(config::write_timer_change(&context));
+        *self = Daemon { task: Some(uuid), started: context.timer, length: work, pause: false,
+                         durations: context.durations.overridden_by(Some(work),
+                                                                    Some(short_break),
+                                                                    Some(long_break)),
+                         paused_at: None, paused_seconds: 0, max_pause: 0,
+                         suspended: chrono::Duration::zero() };
+        Ok(uuid)
+    }
+
+    fn stop(&mut self) -> Result<&'static str, Error> {
+        let _lock = try!(config::lock_task_file());
+        let mut context = try!(self.context());
+        if self.started.is_none() && context.timer.is_none() && !context.has_ongoing_task() {
+            return Ok("idle");
+        }
+        let answer = if self.pause {
+            "break_over"
+        } else {
+            if let Some(task) = context.get_ongoing_task() {
+                let start = self.started.or(context.timer).unwrap_or(chrono::Local::now());
+                try!(config::append_history(&LogEntry::new(start, task.uuid,
+                                                           Outcome::Interrupted, 0)));
+            }
+            "voided"
+        };
+        context.interrupt();
+        try!(config::write_timer_change(&context));
+        self.reset();
+        Ok(answer)
+    }
+
+    fn pause(&mut self) -> Result<&'static str, Error> {
+        if self.started.is_none() {
+            return Ok("idle");
+        }
+        let _lock = try!(config::lock_task_file());
+        let mut context = try!(self.context());
+        context.timer = self.started;
+        context.paused_at = self.paused_at;
+        if !try!(context.pause_timer()) {
+            return Ok("already_paused");
+        }
+        try!(config::write_timer_change(&context));
+        self.paused_at = context.paused_at;
+        self.max_pause = context.max_pause;
+        Ok("paused")
+    }
+
+    fn resume(&mut self) -> Result<&'static str, Error> {
+        if self.started.is_none() {
+            return Ok("idle");
+        }
+        let _lock = try!(config::lock_task_file());
+        let mut context = try!(self.context());
+        context.paused_at = self.paused_at;
+        context.paused_seconds = self.paused_seconds;
+        if !context.resume_timer() {
+            return Ok("not_paused");
+        }
+        try!(config::write_timer_change(&context));
+        self.paused_at = None;
+        self.paused_seconds = context.paused_seconds;
+        Ok("resumed")
+    }
+
+    fn status(&self) -> Result<String, Error> {
+        // Clients holding the task file lock ask for the status: the
+        // task file is only read, which needs no lock.
+        let mut context = try!(config::create_context());
+        context.timer = self.started;
+        context.pause = self.pause;
+        Ok(match self.started {
+            Some(s) => format!("{} {} {} {} {}", context.phase().name(), s.to_rfc3339(),
+                               self.length, self.paused_seconds,
+                               self.paused_at.map_or("-".to_string(), |x| x.to_rfc3339())),
+            None => "idle - - 0 -".to_string()
+        })
+    }
+
+    fn woke_up(&mut self, suspended: chrono::Duration) {
+        writeln!(&mut io::stderr(), "Woke up after {} asleep.",
+                 utils::duration_to_string(suspended)).unwrap();
+        if self.started.is_some() {
+            self.suspended = self.suspended + suspended;
+        }
+    }
+
+    /// What to tell about a suspend when the pomodoro or break ends.
+    fn suspended_note(&self) -> String {
+        match self.suspended.num_minutes() {
+            0 => String::new(),
+            _ => format!(" (the computer slept for {} of it)",
+                         utils::duration_to_string(self.suspended))
+        }
+    }
+
+    /// End the pomodoro or the break once its time is over, or once
+    /// it has been paused for too long.
+    fn tick(&mut self) -> Result<(), Error> {
+        if let Some(p) = self.paused_at {
+            if self.max_pause > 0
+                && chrono::Local::now() - p >= chrono::Duration::minutes(self.max_pause as i64) {
+                return self.void_paused();
+            }
+            return Ok(());
+        }
+        let deadline = match self.started {
+            Some(s) => s + chrono::Duration::minutes(self.length as i64)
+                + chrono::Duration::seconds(self.paused_seconds),
+            None => return Ok(())
+        };
+        if chrono::Local::now() < deadline {
+            return Ok(());
+        }
+        match self.pause {
+            false => self.end_pomodoro(),
+            true => self.end_break()
+        }
+    }
+
+    fn void_paused(&mut self) -> Result<(), Error> {
+        let max_pause = self.max_pause;
+        let answer = try!(self.stop());
+        let context = {
+            let _lock = try!(config::lock_task_file());
+            try!(self.context())
+        };
+        let title = match answer {
+            "voided" => "Pomodoro voided !",
+            _ => "Break is over !"
+        };
+        notify_according_to_context(&context, title,
+                                    &format!("Paused for more than {} minutes", max_pause));
+        Ok(())
+    }
+
+    fn end_pomodoro(&mut self) -> Result<(), Error> {
+        let (context, pause_length) = {
+            let _lock = try!(config::lock_task_file());
+            let mut context = try!(self.context());
+            if context.timer != self.started {
+                return Ok(self.reset());
+            }
+            context.durations = self.durations;
+            // The task may have been deleted in the meantime: the
+            // pomodoro still counts.
+            let uuid = self.task.unwrap_or(Uuid::nil());
+            if let Some(task) = context.tasks.iter_mut().find(|x| x.uuid == uuid) {
+                task.after_doing_pomodoro();
+            }
+            context.increment_pomodoro_count();
+            let pause_length = if context.should_be_long_pause() {
+                self.durations.long_break
+            } else {
+                self.durations.short_break
+            };
+            try!(config::append_history(&LogEntry::new(self.started.unwrap(), uuid,
+                                                       Outcome::Completed, pause_length)));
+            context.pause = true;
+            context.timer = Some(chrono::Local::now());
+            context.timer_length = Some(pause_length);
+            context.clear_paused();
+            try!(config::write_timer_change(&context));
+            (context, pause_length)
+        };
+        let note = self.suspended_note();
+        self.task = None;
+        self.pause = true;
+        self.started = context.timer;
+        self.length = pause_length;
+        self.paused_seconds = 0;
+        self.suspended = chrono::Duration::zero();
+        notify_according_to_context(&context, "Pomodoro done !",
+                                    &format!("Take a {} minute break !{}", pause_length, note));
+        Ok(())
+    }
+
+    fn end_break(&mut self) -> Result<(), Error> {
+        let context = {
+            let _lock = try!(config::lock_task_file());
+            let mut context = try!(self.context());
+            if context.timer != self.started {
+                return Ok(self.reset());
+            }
+            context.pause = false;
+            context.timer = None;
+            context.timer_length = None;
+            context.timer_pid = None;
+            context.clear_paused();
+            try!(config::write_timer_change(&context));
+            context
+        };
+        let note = self.suspended_note();
+        self.reset();
+        notify_according_to_context(&context, "Break is over !",
+                                    &format!("Start a new task{}", note));
+        Ok(())
+    }
+
+    fn handle(&mut self, stream: UnixStream) -> Result<(), Error> {
+        // A client that does not write its request must not block the timer.
+        try!(stream.set_nonblocking(false));
+        try!(stream.set_read_timeout(Some(time::Duration::from_secs(1))));
+        let mut line = String::new();
+        try!(BufReader::new(&stream).read_line(&mut line));
+        // Clients waiting for the daemon to start connect without a word.
+        if line.is_empty() {
+            return Ok(());
+        }
+        let answer = match Request::from_line(&line).map_err(|e| Error::InvalidCommand(e))
+            .and_then(|x| self.answer(x)) {
+            Ok(fields) => format!("OK {}", fields),
+            Err(e) => format!("ERR {} {}", e.exit_code(), escape(&e.to_string()))
+        };
+        try!((&stream).write_all(format!("{}\n", answer).as_bytes()));
+        Ok(())
+    }
+}
+
+/// Failing to notify should not stop the pomodoro cycle: we fall back
+/// to printing the notification and go on.
+fn notify_according_to_context(context: &Context, notif_title: &str, notif_text: &str) {
+    let notified = context.use_notification && match utils::notify(notif_title, notif_text) {
+        Ok(()) => true,
+        Err(e) => { writeln!(&mut io::stderr(), "{}", e).unwrap(); false }
+    };
+    if !notified {
+        println!("{} {}", notif_title, notif_text);
+    }
+    if context.use_sound {
+        if let Err(e) = utils::ding() {
+            writeln!(&mut io::stderr(), "{}", e).unwrap();
+        }
+    }
+}
+
+/// Listen for requests and time pomodori, until killed.
+pub fn serve() -> Result<(), Error> {
+    // Daemons started at the same time would take the socket from
+    // each other: only the one holding the lock may touch it.
+    let lock = try!(OpenOptions::new().write(true).create(true)
+        .open(try!(config::get_runtime_path_for(LOCK_FILE_NAME))));
+    if unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        return Err(Error::Daemon("a daemon is already running".to_string()));
+    }
+    let path = try!(socket_path());
+    // A daemon that was killed leaves its socket behind.
+    let _ = fs::remove_file(&path);
+    let listener = try!(UnixListener::bind(&path));
+    try!(listener.set_nonblocking(true));
+    let mut daemon = Daemon::new();
+    let mut watch = utils::SuspendWatch::new();
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => if let Err(e) = daemon.handle(stream) {
+                writeln!(&mut io::stderr(), "{}", e).unwrap();
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => (),
+            Err(e) => return Err(Error::Io(e))
+        }
+        let suspended = watch.check();
+        if suspended > chrono::Duration::zero() {
+            daemon.woke_up(suspended);
+        }
+        // Retrying a transition that failed would fail again and again.
+        if let Err(e) = daemon.tick() {
+            writeln!(&mut io::stderr(), "{}", e).unwrap();
+            daemon.reset();
+        }
+        thread::sleep(time::Duration::from_millis(POLL_INTERVAL_MS));
+    }
+}
+
+#[test]
+fn test_protocol() {
+    let request = Request::Start("12".to_string(), 25, 5, 30);
+    assert_eq!(Request::from_line(&format!("{}\n", request.to_line())), Ok(request));
+    assert_eq!(Request::from_line("STATUS\n"), Ok(Request::Status));
+    assert_eq!(Request::from_line(&Request::Pause.to_line()), Ok(Request::Pause));
+    assert!(Request::from_line("START 12 25").is_err());
+    assert_eq!(parse_response("OK work 2016-03-10T10:00:00+01:00 25\n").unwrap(),
+               ["work", "2016-03-10T10:00:00+01:00", "25"]);
+    let message = "Several tasks match:\n1 a\\b";
+    match parse_response(&format!("ERR 4 {}\n", escape(message))) {
+        Err(Error::Remote(4, ref m)) => assert_eq!(m, message),
+        r => panic!("unexpected response {:?}", r)
+    }
+}
@@ -0,0 +1,101 @@
+use std::error;
+use std::fmt;
+use std::io;
+use pomorust::model::IdentificationError;
+
+/// Everything that can go wrong while running pomorust.
+#[derive(Debug)]
+pub enum Error {
+    /// The task file could not be read: line and reason
+    Parse(usize, String),
+    /// The identifier given by the user does not point to a single task
+    Identification(String, IdentificationError),
+    /// A pomodoro is already running
+    TaskOngoing,
+    /// The command needs a running pomodoro
+    NoOngoingTask,
+    /// The command line could not be understood
+    InvalidCommand(String),
+    /// Config or data directory could not be used
+    Config(String),
+    /// Reading or writing files failed
+    Io(io::Error),
+    /// The ding could not be played
+    Audio(String),
+    /// The desktop notification could not be shown
+    Notification(String),
+    /// The daemon could not be reached, or did not make sense
+    Daemon(String),
+    /// The daemon failed to carry out a request: exit code and message
+    Remote(i32, String)
+}
+
+impl Error {
+    /// Each kind of error exits with its own code, so that scripts
+    /// calling pomorust can tell them apart. 1 is left to argument
+    /// parsing errors.
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            Error::InvalidCommand(_) => 2,
+            Error::Parse(_, _) => 3,
+            Error::Identification(_, _) => 4,
+            Error::TaskOngoing => 5,
+            Error::Config(_) => 6,
+            Error::Io(_) => 7,
+            Error::Audio(_) => 8,
+            Error::Notification(_) => 9,
+            Error::NoOngoingTask => 10,
+            Error::Daemon(_) => 11,
+            Error::Remote(code, _) => code
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Parse(line, ref reason) =>
+                write!(f, "Error in the task file, line {} : {}", line, reason),
+            Error::Identification(ref identifier, IdentificationError::NoMatch) =>
+                write!(f, "No task matches \"{}\".", identifier),
+            Error::Identification(ref identifier, IdentificationError::AmbiguousMatch(ref c)) =>
+                write!(f, "Several tasks match \"{}\", give a short id or a longer UUID :\n{}",
+                       identifier, c.join("\n")),
+            Error::TaskOngoing =>
+                write!(f, "You are already doing a task ! Mark it as done if you're \
+                          over before starting a new one."),
+            Error::NoOngoingTask => write!(f, "You are not doing a pomodoro right now."),
+            Error::InvalidCommand(ref reason) => write!(f, "Invalid command : {}", reason),
+            Error::Config(ref reason) => write!(f, "Configuration problem : {}", reason),
+            Error::Io(ref err) => write!(f, "Could not access the task file : {}", err),
+            Error::Audio(ref reason) => write!(f, "Could not play sound : {}", reason),
+            Error::Notification(ref reason) => write!(f, "Could not notify : {}", reason),
+            Error::Daemon(ref reason) => write!(f, "Could not talk to the daemon : {}", reason),
+            Error::Remote(_, ref message) => write!(f, "{}", message)
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Parse(_, _) => "invalid task file",
+            Error::Identification(_, _) => "invalid task identifier",
+            Error::TaskOngoing => "task already ongoing",
+            Error::NoOngoingTask => "no ongoing task",
+            Error::InvalidCommand(_) => "invalid command",
+            Error::Config(_) => "configuration problem",
+            Error::Io(ref err) => err.description(),
+            Error::Audio(_) => "sound failure",
+            Error::Notification(_) => "notification failure",
+            Error::Daemon(_) => "daemon unreachable",
+            Error::Remote(_, _) => "daemon failure"
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
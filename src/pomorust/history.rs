@@ -0,0 +1,104 @@
+use uuid::Uuid;
+use chrono;
+use chrono::NaiveDate;
+use pomorust::utils::str_to;
+
+/// How a pomodoro ended.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Completed,
+    Interrupted
+}
+
+/// One pomodoro, as recorded in the history file.
+#[derive(Debug)]
+pub struct LogEntry {
+    /// When the pomodoro was started
+    pub start: chrono::DateTime<chrono::Local>,
+    /// When it ended, or was stopped
+    pub end: chrono::DateTime<chrono::Local>,
+    /// Task worked upon
+    pub task: Uuid,
+    pub outcome: Outcome,
+    /// Length, in minutes, of the pause following the pomodoro
+    pub break_length: u16
+}
+
+impl LogEntry {
+    pub fn new(start: chrono::DateTime<chrono::Local>, task: Uuid,
+               outcome: Outcome, break_length: u16) -> LogEntry {
+        LogEntry { start: start,
+                   end: chrono::Local::now(),
+                   task: task,
+                   outcome: outcome,
+                   break_length: break_length }
+    }
+
+    pub fn to_record(&self) -> Vec<String> {
+        let outcome = match self.outcome {
+            Outcome::Completed => "completed",
+            Outcome::Interrupted => "interrupted"
+        };
+        vec!(self.start.to_rfc3339(), self.end.to_rfc3339(), self.task.to_string(),
+             outcome.to_string(), self.break_length.to_string())
+    }
+
+    pub fn from_record(elements: &[String]) -> Result<LogEntry, String> {
+        if elements.len() < 5 {
+            return Err("missing fields in a history entry.".to_string());
+        }
+        let start = try!(elements[0].parse::<chrono::DateTime<chrono::Local>>()
+            .map_err(|_| "start date not parsable.".to_string()));
+        let end = try!(elements[1].parse::<chrono::DateTime<chrono::Local>>()
+            .map_err(|_| "end date not parsable.".to_string()));
+        let task = try!(Uuid::parse_str(&elements[2])
+            .map_err(|_| "uuid not parsable.".to_string()));
+        let outcome = match &elements[3][..] {
+            "completed" => Outcome::Completed,
+            "interrupted" => Outcome::Interrupted,
+            _ => return Err("outcome not parsable.".to_string())
+        };
+        let break_length = try!(elements[4].parse()
+            .map_err(|_| "break length not parsable.".to_string()));
+        Ok(LogEntry { start: start,
+                      end: end,
+                      task: task,
+                      outcome: outcome,
+                      break_length: break_length })
+    }
+
+    /// Does this entry match the dates (inclusive) and task given ?
+    pub fn matches(&self, since: Option<NaiveDate>, until: Option<NaiveDate>,
+                   task: Option<&Uuid>) -> bool {
+        let day = self.start.naive_local().date();
+        since.map_or(true, |x| day >= x)
+            && until.map_or(true, |x| day <= x)
+            && task.map_or(true, |x| *x == self.task)
+    }
+
+    pub fn to_log_line(&self, description: &str) -> String {
+        let outcome_sign = match self.outcome {
+            Outcome::Completed => "+",
+            Outcome::Interrupted => "x"
+        };
+        format!("{} {} - {}  {}  {: <60} {} min break",
+                outcome_sign, self.start.format("%Y-%m-%d %H:%M"),
+                self.end.format("%H:%M"), self.task.to_string()[..8].to_string(),
+                str_to(description, 60), self.break_length)
+    }
+}
+
+#[test]
+fn test_log_entry_matches() {
+    let task = Uuid::new_v4();
+    let entry = LogEntry::new(chrono::Local::now(), task, Outcome::Completed, 5);
+    let today = chrono::Local::now().naive_local().date();
+    let yesterday = today.pred();
+    assert!(entry.matches(None, None, None));
+    assert!(entry.matches(Some(today), Some(today), Some(&task)));
+    assert!(!entry.matches(None, Some(yesterday), None));
+    assert!(!entry.matches(None, None, Some(&Uuid::new_v4())));
+    let parsed = LogEntry::from_record(&entry.to_record()).unwrap();
+    assert_eq!(parsed.outcome, Outcome::Completed);
+    assert_eq!(parsed.break_length, 5);
+}
@@ -1,4 +1,11 @@
 pub mod model;
 pub mod config;
+pub mod record;
 pub mod actions;
 pub mod utils;
+pub mod error;
+pub mod history;
+pub mod stats;
+pub mod output;
+pub mod daemon;
+pub mod tui;
@@ -1,8 +1,112 @@
+use std::cmp;
+use std::cmp::Ordering;
+use std::str::FromStr;
 use uuid::Uuid;
 use chrono;
-use pomorust::utils::{MaybeLocalDate, parse_maybe_local_date, parse_maybe_string, str_to};
+use chrono::NaiveDate;
+use pomorust::record::{parse_records, serialize_record};
+use pomorust::error::Error;
+use pomorust::history::{LogEntry, Outcome};
+use pomorust::output::DEFAULT_STATUS_TEMPLATE;
+use pomorust::utils::{MaybeLocalDate, parse_maybe_local_date, parse_maybe_string, str_to,
+                      duration_to_string, json_string, json_maybe_date, json_maybe_string,
+                      tsv_field};
 
 
+/// Interruptions, as defined by the pomodoro technique, are either
+/// internal (noted ') or external (noted -).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InterruptionKind {
+    Internal,
+    External
+}
+
+impl InterruptionKind {
+    pub fn to_mark(&self) -> &'static str {
+        match *self {
+            InterruptionKind::Internal => "'",
+            InterruptionKind::External => "-"
+        }
+    }
+
+    pub fn from_mark(mark: &str) -> Option<InterruptionKind> {
+        match mark {
+            "'" => Some(InterruptionKind::Internal),
+            "-" => Some(InterruptionKind::External),
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Interruption {
+    pub kind: InterruptionKind,
+    /// When the interruption was noted
+    pub date: chrono::DateTime<chrono::Local>,
+    /// Optional note on what interrupted the user
+    pub note: String
+}
+
+impl Interruption {
+    pub fn new(kind: InterruptionKind, note: &str) -> Interruption {
+        Interruption { kind: kind, date: chrono::Local::now(), note: note.to_string() }
+    }
+}
+
+/// A timestamped note on a task.
+#[derive(Debug, Clone)]
+pub struct Note {
+    pub date: chrono::DateTime<chrono::Local>,
+    pub text: String
+}
+
+/// In the text given to the editor, each note starts with this
+/// marker, followed by its date.
+const NOTE_MARKER: &'static str = "--- ";
+
+impl Note {
+    pub fn new(text: &str) -> Note {
+        Note { date: chrono::Local::now(), text: text.to_string() }
+    }
+
+    /// Turn notes into a text the user can edit.
+    pub fn to_editable_text(notes: &[Note]) -> String {
+        notes.iter()
+            .map(|x| format!("{}{}\n{}\n", NOTE_MARKER, x.date.to_rfc3339(), x.text))
+            .collect::<Vec<String>>()
+            .concat()
+    }
+
+    /// Read back notes edited by the user. Text before the first
+    /// marker, or after a marker without a valid date, is a new note.
+    pub fn from_editable_text(text: &str) -> Vec<Note> {
+        let mut notes = vec!();
+        let mut current = Note::new("");
+        let mut lines: Vec<&str> = vec!();
+        for line in text.lines() {
+            if line.starts_with(NOTE_MARKER) {
+                current.text = lines.join("\n").trim().to_string();
+                if !current.text.is_empty() {
+                    notes.push(current);
+                }
+                lines.clear();
+                current = match line[NOTE_MARKER.len()..].trim()
+                    .parse::<chrono::DateTime<chrono::Local>>() {
+                    Ok(d) => Note { date: d, text: String::new() },
+                    Err(_) => Note::new("")
+                };
+            } else {
+                lines.push(line);
+            }
+        }
+        current.text = lines.join("\n").trim().to_string();
+        if !current.text.is_empty() {
+            notes.push(current);
+        }
+        notes
+    }
+}
+
 #[derive(Debug)]
 pub struct Task {
     /// Main description for the task
@@ -13,31 +117,65 @@ pub struct Task {
     pomodori_count: u16,
     /// Number of pomodori needed according to user
     pomodori_estimate: u16,
+    /// Number of pomodori stopped before their end
+    pomodori_interrupted: u16,
     /// Is the user currently doing a pomodoro over this task ?
     pub is_ongoing: bool,
-    /// Comment
-    comment: String,
-    /// A general idea of what kind of activity this task is
-    kind: Option<String>,
+    /// Notes taken on the task, oldest first
+    notes: Vec<Note>,
+    /// Short number identifying the task, 0 until it is added to
+    /// the context; it does not change until the task is archived
+    id: u32,
+    /// Labels telling what kind of activity this task is
+    tags: Vec<String>,
+    /// Project the task belongs to, sub-projects being separated
+    /// by slashes, e.g. "work/clientA/api"
+    project: Option<String>,
     /// When (and if) this task was started for the first time
     start_date: MaybeLocalDate,
     /// When (and if) this task was finished
-    end_date: MaybeLocalDate
+    end_date: MaybeLocalDate,
+    /// When this task was finished before being reopened, oldest first
+    previous_end_dates: Vec<chrono::DateTime<chrono::Local>>,
+    /// Interruptions noted while doing pomodori on this task
+    interruptions: Vec<Interruption>,
+    /// Day this task was put on the "To Do Today" sheet for; tasks
+    /// without it stay in the activity inventory
+    planned_for: Option<NaiveDate>,
+    /// When this task was jotted down as unplanned and urgent, if it was
+    unplanned: MaybeLocalDate,
+    /// Start of the pomodoro during which this task was jotted down
+    captured_during: MaybeLocalDate
 }
 
 impl Task {
-    pub fn new(desc: &str, estimate: u16, kind: Option<String>) -> Task {
+    pub fn new(desc: &str, estimate: u16, tags: Vec<String>) -> Task {
         Task {
             description: desc.to_string(),
             uuid: Uuid::new_v4(),
             pomodori_count: 0,
             pomodori_estimate: estimate,
-            comment: "".to_string(),
-            kind: kind,
+            pomodori_interrupted: 0,
+            notes: vec!(),
+            id: 0,
+            tags: vec!(),
+            project: None,
             is_ongoing: false,
             start_date: None,
-            end_date: None
+            end_date: None,
+            previous_end_dates: vec!(),
+            interruptions: vec!(),
+            planned_for: None,
+            unplanned: None,
+            captured_during: None
+        }.with_tags(tags)
+    }
+
+    fn with_tags(mut self, tags: Vec<String>) -> Task {
+        for tag in tags {
+            self.add_tag(&tag);
         }
+        self
     }
 
     pub fn before_starting_pomodoro(&mut self) {
@@ -52,14 +190,58 @@ impl Task {
         self.is_ongoing = false;
     }
 
+    /// The pomodoro was stopped before its end: it does not count.
+    pub fn interrupt_pomodoro(&mut self) {
+        self.pomodori_interrupted += 1;
+        self.is_ongoing = false;
+    }
+
+    pub fn add_interruption(&mut self, interruption: Interruption) {
+        self.interruptions.push(interruption);
+    }
+
+    /// Interruptions noted since a given date.
+    pub fn interruptions_since(&self, date: chrono::DateTime<chrono::Local>) -> Vec<&Interruption> {
+        self.interruptions.iter().filter(|x| x.date >= date).collect()
+    }
+
+    /// Interruptions, as marks of the pomodoro technique (e.g. "''-").
+    pub fn interruption_marks(&self) -> String {
+        self.interruptions.iter().map(|x| x.kind.to_mark()).collect::<Vec<&str>>().concat()
+    }
+
     pub fn finish(&mut self) {
         self.end_date = Some(chrono::Local::now())
     }
 
+    /// Make a finished task current again, remembering when it was
+    /// finished. Return false if the task was not finished.
+    pub fn reopen(&mut self) -> bool {
+        match self.end_date.take() {
+            Some(d) => {
+                self.previous_end_dates.push(d);
+                true
+            },
+            None => false
+        }
+    }
+
+    pub fn previous_end_dates(&self) -> &[chrono::DateTime<chrono::Local>] {
+        &self.previous_end_dates
+    }
+
     fn increment_pomodoro(&mut self) {
         self.pomodori_count += 1;
     }
 
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn has_short_id(&self, identifier: &str) -> bool {
+        self.id != 0 && identifier.parse::<u32>().ok() == Some(self.id)
+    }
+
     pub fn can_be_identified_by(&self, identifier: &str) -> bool {
         self.uuid.to_string().starts_with(identifier)
     }
@@ -68,47 +250,425 @@ impl Task {
         self.end_date.is_some()
     }
 
-    pub fn to_csv(&self) -> String {
+    /// Put the task on the sheet of a given day, or back to the
+    /// activity inventory.
+    pub fn plan(&mut self, day: Option<NaiveDate>) {
+        self.planned_for = day;
+    }
+
+    /// Something urgent came up: it goes straight to today's sheet.
+    pub fn mark_as_unplanned(&mut self) {
+        let now = chrono::Local::now();
+        self.unplanned = Some(now);
+        self.planned_for = Some(now.naive_local().date());
+    }
+
+    /// Remember the pomodoro that was interrupted by this task.
+    pub fn set_captured_during(&mut self, pomodoro_start: MaybeLocalDate) {
+        self.captured_during = pomodoro_start;
+    }
+
+    pub fn is_unplanned(&self) -> bool {
+        self.unplanned.is_some()
+    }
+
+    /// Was this task jotted down as unplanned and urgent on that day ?
+    /// Left unfinished, it is carried over like any other task.
+    pub fn is_unplanned_on(&self, day: NaiveDate) -> bool {
+        self.unplanned.map_or(false, |x| x.naive_local().date() == day)
+    }
+
+    pub fn unplanned_date(&self) -> MaybeLocalDate {
+        self.unplanned
+    }
+
+    pub fn captured_during(&self) -> MaybeLocalDate {
+        self.captured_during
+    }
+
+    pub fn planned_for(&self) -> Option<NaiveDate> {
+        self.planned_for
+    }
+
+    /// Should this task be done today ? Unfinished tasks planned for
+    /// previous days are carried over.
+    pub fn is_on_sheet_of(&self, today: NaiveDate) -> bool {
+        !self.is_finished() && self.planned_for.map_or(false, |x| x <= today)
+    }
+
+    pub fn is_carried_over(&self, today: NaiveDate) -> bool {
+        self.is_on_sheet_of(today) && self.planned_for.map_or(false, |x| x < today)
+    }
+
+    pub fn notes(&self) -> &[Note] {
+        &self.notes
+    }
+
+    pub fn add_note(&mut self, text: &str) {
+        self.notes.push(Note::new(text));
+    }
+
+    /// Change the text of a note, counting from 1. Fails if there
+    /// is no such note.
+    pub fn replace_note(&mut self, number: usize, text: &str) -> bool {
+        if number == 0 || number > self.notes.len() {
+            return false;
+        }
+        self.notes[number - 1].text = text.to_string();
+        true
+    }
+
+    /// Remove a note, counting from 1. Fails if there is no such note.
+    pub fn remove_note(&mut self, number: usize) -> bool {
+        if number == 0 || number > self.notes.len() {
+            return false;
+        }
+        self.notes.remove(number - 1);
+        true
+    }
+
+    pub fn set_notes(&mut self, notes: Vec<Note>) {
+        self.notes = notes;
+    }
+
+    pub fn pomodori_count(&self) -> u16 {
+        self.pomodori_count
+    }
+
+    pub fn pomodori_estimate(&self) -> u16 {
+        self.pomodori_estimate
+    }
+
+    /// Pomodori spent beyond the estimate (negative if under it).
+    pub fn estimate_error(&self) -> i32 {
+        self.pomodori_count as i32 - self.pomodori_estimate as i32
+    }
+
+    /// Pomodori spent for each pomodoro estimated.
+    pub fn overrun_ratio(&self) -> f32 {
+        self.pomodori_count as f32 / cmp::max(self.pomodori_estimate, 1) as f32
+    }
+
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|x| x == tag)
+    }
+
+    /// Blank and already present tags are ignored.
+    pub fn add_tag(&mut self, tag: &str) {
+        let tag = tag.trim();
+        if !tag.is_empty() && !self.has_tag(tag) {
+            self.tags.push(tag.to_string());
+        }
+    }
+
+    pub fn remove_tag(&mut self, tag: &str) {
+        self.tags.retain(|x| x != tag.trim());
+    }
+
+    pub fn project(&self) -> Option<&String> {
+        self.project.as_ref()
+    }
+
+    /// An empty project removes the task from its project.
+    pub fn set_project(&mut self, project: &str) {
+        self.project = parse_maybe_string(project.trim().trim_matches('/'));
+    }
+
+    /// Is the task part of this project, or of one of its sub-projects ?
+    pub fn is_in_project(&self, project: &str) -> bool {
+        let project = project.trim_matches('/');
+        self.project.as_ref().map_or(false, |x| {
+            x == project || x.starts_with(&format!("{}/", project))
+        })
+    }
+
+    /// The project, followed by every project it is a sub-project of:
+    /// "work/clientA", then "work".
+    pub fn project_hierarchy(&self) -> Vec<String> {
+        let mut projects = vec!();
+        if let Some(ref p) = self.project {
+            let mut current = &p[..];
+            projects.push(current.to_string());
+            while let Some(i) = current.rfind('/') {
+                current = &current[..i];
+                projects.push(current.to_string());
+            }
+        }
+        projects
+    }
+
+    /// Project and tags, as displayed in listings: "work/clientA #review".
+    fn labels(&self) -> String {
+        self.project.iter().cloned()
+            .chain(self.tags.iter().map(|x| format!("#{}", x)))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    pub fn end_date(&self) -> MaybeLocalDate {
+        self.end_date
+    }
+
+    /// Turn the task into a list of fields, to be stored in the task file.
+    pub fn to_record(&self) -> Vec<String> {
         let start_date_string = self.start_date.map_or(String::new(), |x|x.to_rfc3339());
         let end_date_string = self.end_date.map_or(String::new(), |x| x.to_rfc3339());
-        format!("{};{};{};{};{};{};{};{};{}\n", self.description, self.uuid,
-                self.pomodori_count, self.pomodori_estimate,
-                self.comment, self.is_ongoing,
-                self.kind.as_ref().unwrap_or(&"".to_string()),
-                start_date_string, end_date_string
-        )
-    }
-
-    pub fn from_csv(line: &str) -> Task {
-        let task_elements = line.split(";").collect::<Vec<&str>>();
-        let desc = task_elements[0];
-        let uuid : Uuid = Uuid::parse_str(task_elements[1])
-            .ok()
-            .expect("Error in the task file : uuid not parsable.");
-        let pomodori_count: u16 = task_elements[2].parse()
-            .ok()
-            .expect("Error in the task file : pomodori count not parsable.");
-        let pomodori_estimate: u16 = task_elements[3].parse()
-            .ok()
-            .expect("Error in the task file : pomodori count not parsable");
-        let comment = task_elements[4];
+        vec!(self.description.clone(), self.uuid.to_string(),
+             self.pomodori_count.to_string(), self.pomodori_estimate.to_string(),
+             String::new(), self.is_ongoing.to_string(), String::new(),
+             start_date_string, end_date_string,
+             self.pomodori_interrupted.to_string(),
+             self.interruptions_to_field(),
+             self.planned_for.map_or(String::new(), |x| x.format(DAY_FORMAT).to_string()),
+             self.unplanned.map_or(String::new(), |x| x.to_rfc3339()),
+             self.captured_during.map_or(String::new(), |x| x.to_rfc3339()),
+             self.notes_to_field(),
+             match self.tags.is_empty() {
+                 true => String::new(),
+                 false => serialize_record(&self.tags)
+             },
+             self.project.clone().unwrap_or(String::new()),
+             self.id.to_string(),
+             match self.previous_end_dates.is_empty() {
+                 true => String::new(),
+                 false => serialize_record(&self.previous_end_dates.iter()
+                                           .map(|x| x.to_rfc3339()).collect::<Vec<String>>())
+             })
+    }
+
+    /// Notes are stored like interruptions: one record per note.
+    fn notes_to_field(&self) -> String {
+        self.notes.iter()
+            .map(|x| serialize_record(&vec!(x.date.to_rfc3339(), x.text.clone())))
+            .collect::<Vec<String>>()
+            .concat()
+    }
+
+    fn notes_from_field(field: &str) -> Result<Vec<Note>, String> {
+        let mut notes = vec!();
+        for (_, record) in parse_records(field) {
+            if record.len() < 2 {
+                return Err("missing fields in a note.".to_string());
+            }
+            let date = try!(record[0].parse::<chrono::DateTime<chrono::Local>>()
+                .map_err(|_| "note date not parsable.".to_string()));
+            notes.push(Note { date: date, text: record[1].clone() });
+        }
+        Ok(notes)
+    }
+
+    /// Interruptions are stored in a single field, holding one
+    /// record per interruption.
+    fn interruptions_to_field(&self) -> String {
+        self.interruptions.iter()
+            .map(|x| serialize_record(&vec!(x.kind.to_mark().to_string(),
+                                            x.date.to_rfc3339(),
+                                            x.note.clone())))
+            .collect::<Vec<String>>()
+            .concat()
+    }
+
+    fn interruptions_from_field(field: &str) -> Result<Vec<Interruption>, String> {
+        let mut interruptions = vec!();
+        for (_, record) in parse_records(field) {
+            if record.len() < 3 {
+                return Err("missing fields in an interruption.".to_string());
+            }
+            let kind = try!(InterruptionKind::from_mark(&record[0])
+                .ok_or("interruption kind not parsable.".to_string()));
+            let date = try!(record[1].parse::<chrono::DateTime<chrono::Local>>()
+                .map_err(|_| "interruption date not parsable.".to_string()));
+            interruptions.push(Interruption { kind: kind, date: date, note: record[2].clone() });
+        }
+        Ok(interruptions)
+    }
+
+    /// Build a task from the fields of the task file; on failure,
+    /// tell what could not be parsed.
+    pub fn from_record(task_elements: &[String]) -> Result<Task, String> {
+        if task_elements.len() < 9 {
+            return Err("missing fields in a task.".to_string());
+        }
+        let desc = &task_elements[0];
+        let uuid : Uuid = try!(Uuid::parse_str(&task_elements[1])
+            .map_err(|_| "uuid not parsable.".to_string()));
+        let pomodori_count: u16 = try!(task_elements[2].parse()
+            .map_err(|_| "pomodori count not parsable.".to_string()));
+        let pomodori_estimate: u16 = try!(task_elements[3].parse()
+            .map_err(|_| "pomodori estimate not parsable.".to_string()));
+        let comment = &task_elements[4];
         let is_ongoing : bool = task_elements[5] == "true";
-        let kind = parse_maybe_string(task_elements[6]);
-        let start_date = parse_maybe_local_date(task_elements[7],
-            "Error in the task file : start date not parsable.");
-        let end_date = parse_maybe_local_date(task_elements[8],
-            "Error in the task file : start date not parsable.");
-        Task {
+        let kind = &task_elements[6];
+        let start_date = try!(parse_maybe_local_date(&task_elements[7],
+            "start date not parsable."));
+        let end_date = try!(parse_maybe_local_date(&task_elements[8],
+            "end date not parsable."));
+        // Interruptions were not recorded by older versions.
+        let pomodori_interrupted: u16 = match optional_field(task_elements, 9) {
+            "" => 0,
+            x => try!(x.parse()
+                .map_err(|_| "interrupted pomodori count not parsable.".to_string()))
+        };
+        let interruptions = try!(Task::interruptions_from_field(optional_field(task_elements, 10)));
+        let planned_for = match optional_field(task_elements, 11) {
+            "" => None,
+            x => Some(try!(NaiveDate::parse_from_str(x, DAY_FORMAT)
+                .map_err(|_| "planned day not parsable.".to_string())))
+        };
+        let unplanned = try!(parse_maybe_local_date(optional_field(task_elements, 12),
+            "unplanned date not parsable."));
+        let captured_during = try!(parse_maybe_local_date(optional_field(task_elements, 13),
+            "capture pomodoro not parsable."));
+        let mut notes = try!(Task::notes_from_field(optional_field(task_elements, 14)));
+        // Tasks used to have a single comment, now the first note.
+        if !comment.is_empty() {
+            notes.insert(0, Note { date: start_date.unwrap_or(chrono::Local::now()),
+                                   text: comment.to_string() });
+        }
+        let mut tags = parse_records(optional_field(task_elements, 15)).into_iter()
+            .next().map_or(vec!(), |(_, x)| x);
+        // Tasks used to have a single kind, now their first tag.
+        if !kind.is_empty() && !tags.contains(kind) {
+            tags.insert(0, kind.to_string());
+        }
+        let project = parse_maybe_string(optional_field(task_elements, 16));
+        // Older tasks are numbered once loaded, see `Context::number_tasks`.
+        let id = match optional_field(task_elements, 17) {
+            "" => 0,
+            x => try!(x.parse().map_err(|_| "short id not parsable.".to_string()))
+        };
+        let mut previous_end_dates = vec!();
+        for (_, record) in parse_records(optional_field(task_elements, 18)) {
+            for date in record.iter() {
+                previous_end_dates.push(try!(date.parse::<chrono::DateTime<chrono::Local>>()
+                    .map_err(|_| "previous end date not parsable.".to_string())));
+            }
+        }
+        Ok(Task {
             description: desc.to_string(),
             uuid: uuid,
             pomodori_count: pomodori_count,
             pomodori_estimate: pomodori_estimate,
-            comment: comment.to_string(),
+            pomodori_interrupted: pomodori_interrupted,
+            notes: notes,
+            id: id,
             is_ongoing: is_ongoing,
-            kind: kind,
+            tags: tags,
+            project: project,
             start_date: start_date,
-            end_date: end_date
+            end_date: end_date,
+            previous_end_dates: previous_end_dates,
+            interruptions: interruptions,
+            planned_for: planned_for,
+            unplanned: unplanned,
+            captured_during: captured_during
+        })
+    }
+
+    pub fn state(&self) -> TaskState {
+        if self.is_ongoing {
+            TaskState::Ongoing
+        } else if self.is_finished() {
+            TaskState::Done
+        } else {
+            TaskState::Open
+        }
+    }
+
+    /// Every information on the task, for the show command.
+    pub fn to_details(&self) -> String {
+        let date_or_none = |x: MaybeLocalDate| x.map_or("-".to_string(),
+                                                       |d| d.format("%Y-%m-%d %H:%M").to_string());
+        let mut lines = vec!(
+            format!("Task        : {}", self.description),
+            format!("ID          : {}", self.id),
+            format!("UUID        : {}", self.uuid),
+            format!("State       : {}", self.state().name()),
+            format!("Tags        : {}", match self.tags.is_empty() {
+                true => "-".to_string(),
+                false => self.tags.join(", ")
+            }),
+            format!("Project     : {}", self.project.as_ref().map_or("-", |x| x)),
+            format!("Pomodori    : {} / {} estimated ({} voided)", self.pomodori_count,
+                    self.pomodori_estimate, self.pomodori_interrupted),
+            format!("Started     : {}", date_or_none(self.start_date)),
+            format!("Finished    : {}", date_or_none(self.end_date)),
+            format!("Planned for : {}", self.planned_for.map_or("-".to_string(), |x| x.to_string())));
+        if let Some(start) = self.start_date {
+            let end = self.end_date.unwrap_or(chrono::Local::now());
+            lines.push(format!("Elapsed     : {}", duration_to_string(end - start)));
+        }
+        if let Some(d) = self.unplanned {
+            lines.push(format!("Unplanned   : jotted down {}", d.format("%Y-%m-%d %H:%M")));
+        }
+        for d in self.previous_end_dates.iter() {
+            lines.push(format!("Reopened    : after being finished {}",
+                               d.format("%Y-%m-%d %H:%M")));
+        }
+        if !self.interruptions.is_empty() {
+            lines.push(format!("Interruptions : {}", self.interruption_marks()));
+            for i in self.interruptions.iter() {
+                lines.push(format!("  {} [{}] {}", i.kind.to_mark(),
+                                   i.date.format("%Y-%m-%d %H:%M"), i.note));
+            }
+        }
+        if !self.notes.is_empty() {
+            lines.push("Notes :".to_string());
+            for (i, note) in self.notes.iter().enumerate() {
+                lines.push(format!("  {}. [{}] {}", i + 1, note.date.format("%Y-%m-%d %H:%M"),
+                                   note.text.replace("\n", "\n     ")));
+            }
         }
+        lines.join("\n")
+    }
+
+    pub fn to_json(&self) -> String {
+        let interruptions = self.interruptions.iter()
+            .map(|x| format!("{{\"kind\":{},\"date\":{},\"note\":{}}}",
+                             json_string(match x.kind {
+                                 InterruptionKind::Internal => "internal",
+                                 InterruptionKind::External => "external"
+                             }),
+                             json_string(&x.date.to_rfc3339()), json_string(&x.note)))
+            .collect::<Vec<String>>();
+        let notes = self.notes.iter()
+            .map(|x| format!("{{\"date\":{},\"text\":{}}}",
+                             json_string(&x.date.to_rfc3339()), json_string(&x.text)))
+            .collect::<Vec<String>>();
+        format!("{{\"uuid\":{},\"description\":{},\"kind\":{},\"state\":{},\
+                 \"pomodori_count\":{},\"pomodori_estimate\":{},\"pomodori_voided\":{},\
+                 \"start_date\":{},\"end_date\":{},\"planned_for\":{},\"unplanned\":{},\
+                 \"interruptions\":[{}],\"notes\":[{}],\"tags\":[{}],\"project\":{},\"id\":{},\"previous_end_dates\":[{}]}}",
+                json_string(&self.uuid.to_string()), json_string(&self.description),
+                json_maybe_string(self.tags.first()), json_string(self.state().name()),
+                self.pomodori_count, self.pomodori_estimate, self.pomodori_interrupted,
+                json_maybe_date(self.start_date), json_maybe_date(self.end_date),
+                self.planned_for.map_or("null".to_string(), |x| json_string(&x.to_string())),
+                json_maybe_date(self.unplanned),
+                interruptions.join(","), notes.join(","),
+                self.tags.iter().map(|x| json_string(x)).collect::<Vec<String>>().join(","),
+                json_maybe_string(self.project.as_ref()), self.id,
+                self.previous_end_dates.iter().map(|x| json_string(&x.to_rfc3339()))
+                    .collect::<Vec<String>>().join(","))
+    }
+
+    /// Tab separated fields, in the order of `output::TASK_TSV_HEADER`.
+    pub fn to_tsv_line(&self) -> String {
+        let date = |x: MaybeLocalDate| x.map_or(String::new(), |d| d.to_rfc3339());
+        vec!(self.uuid.to_string(), tsv_field(&self.description),
+             tsv_field(self.tags.first().map_or("", |x| x)), self.state().name().to_string(),
+             self.pomodori_count.to_string(), self.pomodori_estimate.to_string(),
+             self.pomodori_interrupted.to_string(), date(self.start_date), date(self.end_date),
+             self.planned_for.map_or(String::new(), |x| x.to_string()), date(self.unplanned),
+             self.interruption_marks(), tsv_field(&self.tags.join(",")),
+             tsv_field(self.project.as_ref().map_or("", |x| x)), self.id.to_string(),
+             self.previous_end_dates.iter().map(|x| x.to_rfc3339())
+                 .collect::<Vec<String>>().join(","))
+            .join("\t")
     }
 
     pub fn to_list_line(&self) -> String {
@@ -122,10 +682,11 @@ impl Task {
             },
             true => "!"
         };
-        format!("{0} {1: <38} {2:.<60}  {3: <15} {4} / {5}",
+        format!("{0} {7: >4} {1: <38} {2:.<60}  {3: <15} {4} / {5} {6}",
                 ongoing_sign, self.uuid.to_string(), str_to(&self.description, 60),
-                self.kind.as_ref().unwrap_or(&"".to_string()),
-                self.pomodori_count, self.pomodori_estimate)
+                self.labels(),
+                self.pomodori_count, self.pomodori_estimate,
+                self.interruption_marks(), self.id)
     }
 
     pub fn modify(&mut self, modification: TaskModification) {
@@ -135,23 +696,201 @@ impl Task {
         if modification.pomodoro_estimation.is_some() {
             self.pomodori_estimate = modification.pomodoro_estimation.unwrap();
         }
-        if modification.kind.is_some() {
-            self.kind = modification.kind;
+        for tag in modification.tags_added.iter() {
+            self.add_tag(tag);
+        }
+        for tag in modification.tags_removed.iter() {
+            self.remove_tag(tag);
+        }
+        if let Some(ref project) = modification.project {
+            self.set_project(project);
         }
     }
 }
 
+const DAY_FORMAT: &'static str = "%Y-%m-%d";
+
+/// Fields added to the task file over time are missing from older files.
+fn optional_field(elements: &[String], index: usize) -> &str {
+    elements.get(index).map_or("", |x| x)
+}
+
 impl ToString for Task {
     fn to_string(&self) -> String {
         format!("{}", self.description)
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct TaskModification {
     pub description: Option<String>,
     pub pomodoro_estimation: Option<u16>,
-    pub kind: Option<String>,
+    pub tags_added: Vec<String>,
+    pub tags_removed: Vec<String>,
+    /// Empty to remove the task from its project
+    pub project: Option<String>
+}
+
+/// Criteria narrowing a listing of tasks. Every criterion must be
+/// met; date ranges are inclusive.
+#[derive(Debug, Default)]
+pub struct TaskFilter {
+    /// Tags the task must all have
+    pub tags: Vec<String>,
+    /// Project, sub-projects included
+    pub project: Option<String>,
+    pub state: Option<TaskState>,
+    pub started_since: Option<NaiveDate>,
+    pub started_until: Option<NaiveDate>,
+    pub ended_since: Option<NaiveDate>,
+    pub ended_until: Option<NaiveDate>,
+    /// Case insensitive search in the description
+    pub text: Option<String>
+}
+
+/// A task without the date is outside of any range.
+fn is_in_range(date: MaybeLocalDate, since: Option<NaiveDate>, until: Option<NaiveDate>) -> bool {
+    if since.is_none() && until.is_none() {
+        return true;
+    }
+    date.map_or(false, |x| {
+        let day = x.naive_local().date();
+        since.map_or(true, |s| day >= s) && until.map_or(true, |u| day <= u)
+    })
+}
+
+impl TaskFilter {
+    pub fn matches(&self, task: &Task) -> bool {
+        self.tags.iter().all(|x| task.has_tag(x))
+            && self.project.as_ref().map_or(true, |x| task.is_in_project(x))
+            && self.state.map_or(true, |x| task.state() == x)
+            && is_in_range(task.start_date, self.started_since, self.started_until)
+            && is_in_range(task.end_date, self.ended_since, self.ended_until)
+            && self.text.as_ref().map_or(true, |x| task.description.to_lowercase()
+                                         .contains(&x.to_lowercase()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TaskState {
+    Open,
+    Ongoing,
+    Done
+}
+
+impl TaskState {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            TaskState::Open => "open",
+            TaskState::Ongoing => "ongoing",
+            TaskState::Done => "done"
+        }
+    }
+}
+
+impl FromStr for TaskState {
+    type Err = ();
+    fn from_str(src: &str) -> Result<TaskState, ()> {
+        match src {
+            "open" => Ok(TaskState::Open),
+            "ongoing" => Ok(TaskState::Ongoing),
+            "done" => Ok(TaskState::Done),
+            _ => Err(())
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortKey {
+    /// Order in which tasks were added
+    Creation,
+    Pomodori,
+    Estimate,
+    /// Pomodori spent compared to the estimate
+    Overrun
+}
+
+/// Minutes a timer may be late before it is deemed left behind.
+const STALE_TIMER_GRACE: i64 = 5;
+
+/// What to do with a pomodoro whose timer died with the machine
+/// or the process running it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Recovery {
+    /// Count it as done
+    Count,
+    /// Count it as interrupted
+    Void,
+    /// Leave it to `pomorust repair`
+    Ask
+}
+
+impl FromStr for Recovery {
+    type Err = ();
+    fn from_str(src: &str) -> Result<Recovery, ()> {
+        match src {
+            "count" => Ok(Recovery::Count),
+            "void" => Ok(Recovery::Void),
+            "ask" => Ok(Recovery::Ask),
+            _ => Err(())
+        }
+    }
+}
+
+impl FromStr for SortKey {
+    type Err = ();
+    fn from_str(src: &str) -> Result<SortKey, ()> {
+        match src {
+            "creation" => Ok(SortKey::Creation),
+            "pomodori" => Ok(SortKey::Pomodori),
+            "estimate" => Ok(SortKey::Estimate),
+            "overrun" => Ok(SortKey::Overrun),
+            _ => Err(())
+        }
+    }
+}
+
+/// Sort a listing. Apart from creation, the biggest values come first.
+pub fn sort_tasks(tasks: &mut Vec<&Task>, key: SortKey) {
+    match key {
+        SortKey::Creation => (),
+        SortKey::Pomodori => tasks.sort_by(|a, b| b.pomodori_count.cmp(&a.pomodori_count)),
+        SortKey::Estimate => tasks.sort_by(|a, b| b.pomodori_estimate.cmp(&a.pomodori_estimate)),
+        SortKey::Overrun => tasks.sort_by(|a, b| b.overrun_ratio().partial_cmp(&a.overrun_ratio())
+                                          .unwrap_or(Ordering::Equal))
+    }
+}
+
+/// Length, in minutes, of every step of a pomodoro cycle.
+#[derive(Debug, Clone, Copy)]
+pub struct Durations {
+    /// Length of a pomodoro
+    pub work: u16,
+    /// Length of the pause following a pomodoro
+    pub short_break: u16,
+    /// Length of the pause following a full cycle of pomodori
+    pub long_break: u16,
+    /// How many pomodori make a full cycle
+    pub long_break_interval: u16
+}
+
+impl Durations {
+    pub fn default() -> Durations {
+        Durations { work: 25,
+                    short_break: 5,
+                    long_break: 30,
+                    long_break_interval: 4 }
+    }
+
+    /// Build new durations, replacing the lengths given by the user
+    /// for a single invocation.
+    pub fn overridden_by(&self, work: Option<u16>, short_break: Option<u16>,
+                         long_break: Option<u16>) -> Durations {
+        Durations { work: work.unwrap_or(self.work),
+                    short_break: short_break.unwrap_or(self.short_break),
+                    long_break: long_break.unwrap_or(self.long_break),
+                    long_break_interval: self.long_break_interval }
+    }
 }
 
 pub struct Context {
@@ -159,6 +898,14 @@ pub struct Context {
     pub use_notification: bool,
     /// Should sounds be played
     pub use_sound: bool,
+    /// Length of pomodori and pauses
+    pub durations: Durations,
+    /// Template of `status --short`
+    pub status_template: String,
+    /// Minutes after which a paused pomodoro is voided, 0 for never
+    pub max_pause: u16,
+    /// What to do with a timer left behind by a crash or a reboot
+    pub stale_timer: Recovery,
     /// Listing of tasks recorded by the user
     pub tasks: Vec<Task>,
     /// When was the last pomodoro done ?
@@ -168,72 +915,255 @@ pub struct Context {
     /// If we are doing a pomodoro or a pause, when was it started ?
     pub timer: MaybeLocalDate,
     /// Are we currently during a pause ?
-    pub pause: bool
+    pub pause: bool,
+    /// How long, in minutes, the current pomodoro or pause should last
+    pub timer_length: Option<u16>,
+    /// Process running the current pomodoro or pause
+    pub timer_pid: Option<u32>,
+    /// If the timer is paused, since when ?
+    pub paused_at: MaybeLocalDate,
+    /// Seconds the timer spent paused before `paused_at`
+    pub paused_seconds: i64
+}
+
+/// Step of the pomodoro cycle.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Phase {
+    Idle,
+    Work,
+    ShortBreak,
+    LongBreak
+}
+
+impl Phase {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Phase::Idle => "idle",
+            Phase::Work => "work",
+            Phase::ShortBreak => "short_break",
+            Phase::LongBreak => "long_break"
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum IdentificationError {
     NoMatch,
-    AmbiguousMatch
+    /// Tasks that could be meant, as they should be shown to the user
+    AmbiguousMatch(Vec<String>)
 }
 
 impl Context {
     pub fn display_status(&self) {
         if self.timer.is_some() {
-            let elapsed = chrono::Local::now() - self.timer.unwrap();
+            let elapsed = chrono::Local::now() - self.timer.unwrap() - self.paused_duration();
+            let remaining = self.remaining().unwrap();
+            if let Some(p) = self.paused_at {
+                println!("Paused for {} minutes.", (chrono::Local::now() - p).num_minutes());
+            }
             if !self.pause {
-                let ongoing_task = self.get_ongoing_task().unwrap();
-                println!("Doing a pomodoro : {}.", ongoing_task.to_string());
-                println!("{} minutes done", elapsed.num_minutes());
+                match self.get_ongoing_task() {
+                    Some(t) => println!("Doing a pomodoro : {}.", t.to_string()),
+                    None => println!("Doing a pomodoro, but no task is marked as ongoing.")
+                }
+                println!("{} minutes done, {} minutes left", elapsed.num_minutes(),
+                         remaining.num_minutes());
+                let interruptions = self.get_ongoing_task()
+                    .map_or(vec!(), |x| x.interruptions_since(self.timer.unwrap()));
+                if !interruptions.is_empty() {
+                    let internal = interruptions.iter()
+                        .filter(|x| x.kind == InterruptionKind::Internal).count();
+                    println!("Interruptions : {} internal, {} external", internal,
+                             interruptions.len() - internal);
+                }
             } else {
-                println!("You've been having a break for {} minutes.\
-                         Stop fiddling with this, do someting else !", elapsed.num_minutes());
+                println!("You've been having a break for {} minutes ({} minutes left). \
+                         Stop fiddling with this, do someting else !", elapsed.num_minutes(),
+                         remaining.num_minutes());
             }
         } else {
             println!("Idle.");
         }
     }
 
+    /// Where we are in the pomodoro cycle.
+    pub fn phase(&self) -> Phase {
+        match (self.timer.is_some(), self.pause) {
+            (false, _) => Phase::Idle,
+            (true, false) => Phase::Work,
+            (true, true) => match self.should_be_long_pause() {
+                true => Phase::LongBreak,
+                false => Phase::ShortBreak
+            }
+        }
+    }
+
+    /// Time left before the end of the current pomodoro or pause.
+    pub fn remaining(&self) -> Option<chrono::Duration> {
+        let length = self.timer_length.unwrap_or(match self.phase() {
+            Phase::LongBreak => self.durations.long_break,
+            Phase::ShortBreak => self.durations.short_break,
+            _ => self.durations.work
+        });
+        self.timer.map(|t| t + chrono::Duration::minutes(length as i64) + self.paused_duration()
+                       - chrono::Local::now())
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused_at.is_some()
+    }
+
+    /// How long the current pomodoro or pause has been paused, so far.
+    pub fn paused_duration(&self) -> chrono::Duration {
+        let current = self.paused_at.map_or(chrono::Duration::zero(), |x| chrono::Local::now() - x);
+        chrono::Duration::seconds(self.paused_seconds) + current
+    }
+
+    /// Freeze the remaining time. Return false if already paused.
+    pub fn pause_timer(&mut self) -> Result<bool, Error> {
+        if self.timer.is_none() {
+            return Err(Error::NoOngoingTask);
+        }
+        if self.is_paused() {
+            return Ok(false);
+        }
+        self.paused_at = Some(chrono::Local::now());
+        Ok(true)
+    }
+
+    /// Let the remaining time run again. Return false if not paused.
+    pub fn resume_timer(&mut self) -> bool {
+        match self.paused_at.take() {
+            Some(p) => {
+                self.paused_seconds += (chrono::Local::now() - p).num_seconds();
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Forget pauses of a pomodoro or break, once it is over.
+    pub fn clear_paused(&mut self) {
+        self.paused_at = None;
+        self.paused_seconds = 0;
+    }
+
+    /// Pomodori done in the current cycle.
+    pub fn cycle_position(&self) -> u16 {
+        match self.last_pomodoro_was_recent() {
+            true => self.pomodori_count,
+            false => 0
+        }
+    }
+
     pub fn default() -> Context {
         Context { use_notification: true,
                   use_sound: true,
+                  durations: Durations::default(),
+                  status_template: DEFAULT_STATUS_TEMPLATE.to_string(),
+                  max_pause: 0,
+                  stale_timer: Recovery::Void,
                   tasks: vec!(),
                   last_pomodoro: None,
                   pomodori_count: 0,
                   timer: None,
-                  pause: false }
+                  pause: false,
+                  timer_length: None,
+                  timer_pid: None,
+                  paused_at: None,
+                  paused_seconds: 0 }
     }
 
-    pub fn from_csv_line(line: &str) -> Context {
-        let context_elements = line.split(";").collect::<Vec<&str>>();
-        let last_pomodoro = parse_maybe_local_date(context_elements[0],
-            "Could not parse last pomodoro time");
-        let pomodori_count : u16 = context_elements[1]
-            .parse().ok().expect("Could not parse pomodori_count");
-        let timer = parse_maybe_local_date(context_elements[2],
-            "Could not parse current pomodoro time");
+    pub fn from_record(context_elements: &[String]) -> Result<Context, String> {
+        if context_elements.len() < 4 {
+            return Err("missing fields in the context.".to_string());
+        }
+        let last_pomodoro = try!(parse_maybe_local_date(&context_elements[0],
+            "Could not parse last pomodoro time"));
+        let pomodori_count : u16 = try!(context_elements[1]
+            .parse().map_err(|_| "Could not parse pomodori_count".to_string()));
+        let timer = try!(parse_maybe_local_date(&context_elements[2],
+            "Could not parse current pomodoro time"));
         let pause = context_elements[3] == "true";
-        Context { tasks: vec!(),
-                  use_notification: true,
-                  use_sound: true,
-                  last_pomodoro: last_pomodoro,
-                  pomodori_count: pomodori_count,
-                  timer: timer,
-                  pause: pause }
+        // Older task files did not record the length of the timer.
+        let timer_length = context_elements.get(4).and_then(|x| x.parse().ok());
+        let timer_pid = context_elements.get(5).and_then(|x| x.parse().ok());
+        let paused_at = match context_elements.get(6) {
+            Some(x) => try!(parse_maybe_local_date(x, "Could not parse pause time")),
+            None => None
+        };
+        let paused_seconds = context_elements.get(7).and_then(|x| x.parse().ok()).unwrap_or(0);
+        Ok(Context { tasks: vec!(),
+                     use_notification: true,
+                     use_sound: true,
+                     durations: Durations::default(),
+                     status_template: DEFAULT_STATUS_TEMPLATE.to_string(),
+                     max_pause: 0,
+                     stale_timer: Recovery::Void,
+                     last_pomodoro: last_pomodoro,
+                     pomodori_count: pomodori_count,
+                     timer: timer,
+                     pause: pause,
+                     timer_length: timer_length,
+                     timer_pid: timer_pid,
+                     paused_at: paused_at,
+                     paused_seconds: paused_seconds })
     }
 
-    pub fn add_task(&mut self, task: Task) {
+    pub fn add_task(&mut self, mut task: Task) {
+        task.id = self.next_id();
         self.tasks.push(task);
     }
 
+    /// Short ids start at 1; ids of archived tasks may be used again.
+    fn next_id(&self) -> u32 {
+        self.tasks.iter().map(|x| x.id).max().unwrap_or(0) + 1
+    }
+
+    /// Give a short id to tasks written before short ids existed.
+    pub fn number_tasks(&mut self) {
+        for i in 0..self.tasks.len() {
+            if self.tasks[i].id == 0 {
+                self.tasks[i].id = self.next_id();
+            }
+        }
+    }
+
     /// Get every context-related, not linked to config,
-    /// data and make it into a CSV-like line (used for serialization).
-    pub fn metadata_to_csv_line(&self) -> String {
+    /// data and make it into a list of fields (used for serialization).
+    pub fn metadata_to_record(&self) -> Vec<String> {
         let last_pomodoro_string = self.last_pomodoro.map_or(String::new(), |x|x.to_rfc3339());
         let timer_string = self.timer.map_or(String::new(), |x|x.to_rfc3339());
-        format!("{};{};{};{}\n",
-            last_pomodoro_string, self.pomodori_count,
-            timer_string, self.pause)
+        let timer_length_string = self.timer_length.map_or(String::new(), |x| x.to_string());
+        let timer_pid_string = self.timer_pid.map_or(String::new(), |x| x.to_string());
+        let paused_at_string = self.paused_at.map_or(String::new(), |x| x.to_rfc3339());
+        vec!(last_pomodoro_string, self.pomodori_count.to_string(),
+             timer_string, self.pause.to_string(), timer_length_string,
+             timer_pid_string, paused_at_string, self.paused_seconds.to_string())
+    }
+
+    /// Note an interruption on the task of the current pomodoro.
+    pub fn add_interruption(&mut self, interruption: Interruption) -> Result<(), Error> {
+        if self.pause {
+            return Err(Error::NoOngoingTask);
+        }
+        match self.tasks.iter_mut().find(|x| x.is_ongoing) {
+            Some(task) => { task.add_interruption(interruption); Ok(()) },
+            None => Err(Error::NoOngoingTask)
+        }
+    }
+
+    /// Stop the current pomodoro or pause. An interrupted pomodoro
+    /// is not counted, but recorded as such on its task.
+    pub fn interrupt(&mut self) {
+        if let Some(task) = self.tasks.iter_mut().find(|x| x.is_ongoing) {
+            task.interrupt_pomodoro();
+        }
+        self.pause = false;
+        self.timer = None;
+        self.timer_length = None;
+        self.timer_pid = None;
+        self.clear_paused();
     }
 
     pub fn has_ongoing_task(&self) -> bool {
@@ -244,57 +1174,162 @@ impl Context {
     /// Check if a given identifier identify one and only one
     /// task.
     pub fn is_valid_identifier(&self, identifier: &str) -> Result<(), IdentificationError> {
-        let mut matching = self.tasks
-            .iter()
-            .filter(|&x| x.can_be_identified_by(&identifier));
-        match matching.next() {
-            None => Err(IdentificationError::NoMatch),
-            Some(_) => match matching.next() {
-                None => Ok(()),
-                Some(_) => Err(IdentificationError::AmbiguousMatch)
-            }
+        self.find_index(identifier).map(|_| ())
+    }
+
+    /// Position of the task identified either by its short id or by
+    /// the beginning of its UUID. Short ids win, since a number could
+    /// also be the beginning of a UUID.
+    fn find_index(&self, identifier: &str) -> Result<usize, IdentificationError> {
+        if let Some(i) = self.tasks.iter().position(|x| x.has_short_id(identifier)) {
+            return Ok(i);
         }
+        let matching = self.tasks.iter()
+            .filter(|x| x.can_be_identified_by(identifier))
+            .collect::<Vec<&Task>>();
+        match matching.len() {
+            0 => Err(IdentificationError::NoMatch),
+            1 => Ok(self.tasks.iter().position(|x| x.can_be_identified_by(identifier)).unwrap()),
+            _ => Err(IdentificationError::AmbiguousMatch(
+                matching.iter().map(|x| x.to_list_line()).collect()))
+        }
+    }
+
+    /// Take a task out of the context, unless a pomodoro is being
+    /// done on it.
+    pub fn remove_task(&mut self, identifier: &str) -> Result<Task, Error> {
+        let i = try!(self.find_index(identifier)
+            .map_err(|e| Error::Identification(identifier.to_string(), e)));
+        if self.tasks[i].is_ongoing {
+            return Err(Error::TaskOngoing);
+        }
+        Ok(self.tasks.remove(i))
+    }
+
+    /// Take out the tasks finished before the given date (every finished
+    /// task if there is none), to archive them.
+    pub fn take_finished_tasks(&mut self, finished_before: MaybeLocalDate) -> Vec<Task> {
+        let (finished, kept) = self.tasks.drain(..).partition(|x| {
+            x.end_date.map_or(false, |d| finished_before.map_or(true, |b| d < b))
+        });
+        self.tasks = kept;
+        finished
+    }
+
+    /// Get the one task identified by the given identifier, read-only.
+    pub fn find_task(&self, identifier: &str) -> Result<&Task, Error> {
+        let i = try!(self.find_index(identifier)
+            .map_err(|e| Error::Identification(identifier.to_string(), e)));
+        Ok(&self.tasks[i])
     }
 
-    pub fn get_task(&mut self, identifier: &str) -> &mut Task {
-        self.tasks
-            .iter_mut()
-            .find(|x| x.can_be_identified_by(&identifier))
-            .unwrap()
+    /// Get the one task identified by the given identifier.
+    pub fn get_task(&mut self, identifier: &str) -> Result<&mut Task, Error> {
+        let i = try!(self.find_index(identifier)
+            .map_err(|e| Error::Identification(identifier.to_string(), e)));
+        Ok(&mut self.tasks[i])
     }
 
     pub fn get_current_tasks(&self) -> Vec<&Task> {
         self.tasks.iter().filter(|&x| !x.is_finished()).collect::<Vec<&Task>>()
     }
 
+    /// The "To Do Today" sheet.
+    pub fn get_today_tasks(&self, today: NaiveDate) -> Vec<&Task> {
+        self.tasks.iter().filter(|&x| x.is_on_sheet_of(today)).collect::<Vec<&Task>>()
+    }
+
+    /// The activity inventory: every unfinished task not planned yet.
+    pub fn get_inventory_tasks(&self, today: NaiveDate) -> Vec<&Task> {
+        self.tasks.iter()
+            .filter(|&x| !x.is_finished() && !x.is_on_sheet_of(today))
+            .collect::<Vec<&Task>>()
+    }
+
     pub fn get_all_tasks(&self) -> Vec<&Task> {
         self.tasks.iter().collect::<Vec<&Task>>()
     }
 
-    fn get_ongoing_task(&self) -> Option<&Task> {
+    /// Start of the pomodoro being done, if any.
+    pub fn current_pomodoro(&self) -> MaybeLocalDate {
+        match self.pause || !self.has_ongoing_task() {
+            true => None,
+            false => self.timer
+        }
+    }
+
+    /// Was the timer left behind by a process that is gone (crash,
+    /// reboot...) ? `owner_running` tells if `timer_pid` is still alive;
+    /// after a reboot, another process may have been given this pid, so
+    /// a timer long overdue is stale all the same. Only ask once sure
+    /// the owner does not answer: it ends its timer, however late.
+    pub fn has_stale_timer(&self, owner_running: bool) -> bool {
+        let ongoing = self.tasks.iter().filter(|x| x.is_ongoing).count();
+        let grace = chrono::Duration::minutes(STALE_TIMER_GRACE);
+        match self.remaining() {
+            None => ongoing > 0,
+            Some(r) => ongoing > 1 || !owner_running || r < -grace
+        }
+    }
+
+    /// Clear the timer left behind by a dead process. The pomodoro
+    /// being done, if any, is counted or voided, and returned for
+    /// the history.
+    pub fn recover(&mut self, count: bool) -> Option<LogEntry> {
+        // When the timer would have rung
+        let end = self.remaining().map(|x| chrono::Local::now() + x);
+        let entry = match (self.pause, self.timer, self.get_ongoing_task().map(|x| x.uuid)) {
+            (false, Some(start), Some(uuid)) if count => {
+                let mut entry = LogEntry::new(start, uuid, Outcome::Completed, 0);
+                entry.end = end.unwrap();
+                Some(entry)
+            },
+            (false, Some(start), Some(uuid)) =>
+                Some(LogEntry::new(start, uuid, Outcome::Interrupted, 0)),
+            _ => None
+        };
+        if count && entry.is_some() {
+            if let Some(task) = self.tasks.iter_mut().find(|x| x.is_ongoing) {
+                task.after_doing_pomodoro();
+            }
+            self.increment_pomodoro_count();
+        }
+        for task in self.tasks.iter_mut().filter(|x| x.is_ongoing) {
+            task.interrupt_pomodoro();
+        }
+        self.pause = false;
+        self.timer = None;
+        self.timer_length = None;
+        self.timer_pid = None;
+        self.clear_paused();
+        entry
+    }
+
+    pub fn get_ongoing_task(&self) -> Option<&Task> {
         self.tasks.iter().find(|&x| x.is_ongoing)
     }
 
     /// Context must manage a basic idea of the pomodoro technique: regular pauses.
-    /// After each pomodori, one should take a 5 minute pauses.
-    /// Every four pomodori, a longer pause should be taken.
+    /// After each pomodori, one should take a short pause.
+    /// Every few pomodori (four by default), a longer pause should be taken.
     /// However,pomorust demands that the user regularly inputs on what task he's
     /// working, and the count of successive pomoodori could easily be wrong.
     /// So we will only count pomodori as successive if the last pomodoro count
-    /// was less than 40 minutes ago.
+    /// was less than a pomodoro and a short pause ago, with ten minutes of leeway.
     pub fn last_pomodoro_was_recent(&self) -> bool {
         let right_now = chrono::Local::now();
+        let threshold = (self.durations.work + self.durations.short_break + 10) as i64;
         match self.last_pomodoro {
             None => false,
-            Some(t) => (right_now - t).num_minutes() <= 40
+            Some(t) => (right_now - t).num_minutes() <= threshold
         }
     }
 
     pub fn increment_pomodoro_count(&mut self) {
         if self.last_pomodoro_was_recent() {
             self.pomodori_count += 1 ;
-            if self.pomodori_count > 4 {
-                self.pomodori_count = 0;
+            if self.pomodori_count > self.durations.long_break_interval {
+                self.pomodori_count = 1;
             }
         } else {
             self.pomodori_count = 1;
@@ -303,7 +1338,8 @@ impl Context {
     }
 
     pub fn should_be_long_pause(&self) -> bool {
-        self.last_pomodoro_was_recent() && self.pomodori_count == 4
+        self.last_pomodoro_was_recent()
+            && self.pomodori_count == self.durations.long_break_interval
     }
 
 }
@@ -313,9 +1349,19 @@ fn test_pomodoro_count() {
     let mut context = Context {
         use_notification: false,
         use_sound: false,
+        durations: Durations::default(),
+        status_template: String::new(),
+        max_pause: 0,
+        stale_timer: Recovery::Void,
         tasks: vec!(),
         last_pomodoro: None,
-        pomodori_count: 0
+        pomodori_count: 0,
+        timer: None,
+        pause: false,
+        timer_length: None,
+        timer_pid: None,
+        paused_at: None,
+        paused_seconds: 0
     };
     // First pomodoro of a session
     context.increment_pomodoro_count();
@@ -331,3 +1377,252 @@ fn test_pomodoro_count() {
     context.increment_pomodoro_count();
     assert_eq!(false, context.should_be_long_pause());
 }
+
+#[test]
+fn test_custom_long_break_interval() {
+    let mut context = Context::default();
+    context.durations = Durations { work: 50,
+                                    short_break: 10,
+                                    long_break: 30,
+                                    long_break_interval: 2 };
+    context.increment_pomodoro_count();
+    assert_eq!(false, context.should_be_long_pause());
+    context.increment_pomodoro_count();
+    assert_eq!(true, context.should_be_long_pause());
+    // A new cycle starts after the long pause
+    context.increment_pomodoro_count();
+    assert_eq!(context.pomodori_count, 1);
+}
+
+#[test]
+fn test_interrupt() {
+    let mut context = Context::default();
+    context.add_task(Task::new("Write tests", 2, vec!()));
+    context.tasks[0].before_starting_pomodoro();
+    context.timer = Some(chrono::Local::now());
+    context.timer_pid = Some(42);
+    context.interrupt();
+    assert_eq!(false, context.has_ongoing_task());
+    assert_eq!(context.tasks[0].pomodori_count, 0);
+    assert_eq!(context.tasks[0].pomodori_interrupted, 1);
+    assert!(context.timer.is_none());
+    assert!(context.timer_pid.is_none());
+}
+
+#[test]
+fn test_interruptions_round_trip() {
+    let mut task = Task::new("Answer the phone", 1, vec!());
+    task.add_interruption(Interruption::new(InterruptionKind::Internal, "coffee; \"now\""));
+    task.add_interruption(Interruption::new(InterruptionKind::External, "call\nfrom boss"));
+    let parsed = Task::from_record(&task.to_record()).unwrap();
+    assert_eq!(parsed.interruption_marks(), "'-");
+    assert_eq!(parsed.interruptions[0].note, "coffee; \"now\"");
+    assert_eq!(parsed.interruptions[1].note, "call\nfrom boss");
+}
+
+#[test]
+fn test_today_sheet() {
+    let today = NaiveDate::from_ymd(2016, 3, 10);
+    let mut context = Context::default();
+    context.add_task(Task::new("Planned", 1, vec!()));
+    context.add_task(Task::new("Carried over", 1, vec!()));
+    context.add_task(Task::new("Inventory", 1, vec!()));
+    context.add_task(Task::new("Tomorrow", 1, vec!()));
+    context.tasks[0].plan(Some(today));
+    context.tasks[1].plan(Some(today.pred()));
+    context.tasks[3].plan(Some(today.succ()));
+    let sheet = context.get_today_tasks(today);
+    assert_eq!(sheet.len(), 2);
+    assert!(sheet[1].is_carried_over(today));
+    let inventory = context.get_inventory_tasks(today);
+    assert_eq!(inventory.len(), 2);
+    assert_eq!(inventory[0].description, "Inventory");
+    let parsed = Task::from_record(&context.tasks[0].to_record()).unwrap();
+    assert_eq!(parsed.planned_for(), Some(today));
+}
+
+#[test]
+fn test_unplanned_tasks() {
+    let today = chrono::Local::now().naive_local().date();
+    let mut context = Context::default();
+    context.add_task(Task::new("Interrupted", 1, vec!()));
+    context.tasks[0].before_starting_pomodoro();
+    context.timer = Some(chrono::Local::now());
+    let mut task = Task::new("Urgent", 1, vec!());
+    task.mark_as_unplanned();
+    task.set_captured_during(context.current_pomodoro());
+    assert_eq!(task.captured_during(), context.timer);
+    assert!(task.is_on_sheet_of(today) && task.is_unplanned_on(today));
+    assert!(!task.is_carried_over(today));
+    let tomorrow = today.succ();
+    assert!(!task.is_unplanned_on(tomorrow) && task.is_carried_over(tomorrow));
+    let parsed = Task::from_record(&task.to_record()).unwrap();
+    assert_eq!(parsed.captured_during(), task.captured_during());
+    // Nothing is interrupted during a break.
+    context.pause = true;
+    assert!(context.current_pomodoro().is_none());
+}
+
+#[test]
+fn test_notes() {
+    let mut task = Task::new("Write the docs", 1, vec!());
+    task.add_note("First;\n\"note\"");
+    task.add_note("Second note");
+    let mut record = task.to_record();
+    record[4] = "Old comment".to_string();
+    let mut parsed = Task::from_record(&record).unwrap();
+    assert_eq!(parsed.notes().len(), 3);
+    assert_eq!(parsed.notes()[0].text, "Old comment");
+    assert_eq!(parsed.notes()[1].text, "First;\n\"note\"");
+    assert!(parsed.remove_note(1));
+    assert!(!parsed.remove_note(3));
+    let edited = format!("{}Jotted in the editor\n",
+                         Note::to_editable_text(parsed.notes()).replace("Second", "2nd"));
+    let notes = Note::from_editable_text(&edited);
+    assert_eq!(notes.len(), 2);
+    assert_eq!(notes[0].date, parsed.notes()[0].date);
+    assert_eq!(notes[1].text, "2nd note\nJotted in the editor");
+}
+
+#[test]
+fn test_filter_and_sort() {
+    let mut context = Context::default();
+    context.add_task(Task::new("Write the report", 2, vec!("writing".to_string())));
+    context.add_task(Task::new("Review the REPORT", 1, vec!()));
+    context.add_task(Task::new("Fix the build", 4, vec!()));
+    context.tasks[1].pomodori_count = 3;
+    context.tasks[2].pomodori_count = 2;
+    context.tasks[2].finish();
+    let filter = TaskFilter { text: Some("report".to_string()), ..TaskFilter::default() };
+    assert_eq!(context.get_all_tasks().into_iter().filter(|x| filter.matches(x)).count(), 2);
+    let filter = TaskFilter { tags: vec!("writing".to_string()),
+                              state: Some(TaskState::Open), ..TaskFilter::default() };
+    assert_eq!(context.get_all_tasks().into_iter().filter(|x| filter.matches(x)).count(), 1);
+    let today = chrono::Local::now().naive_local().date();
+    let filter = TaskFilter { ended_since: Some(today), ..TaskFilter::default() };
+    let done = context.get_all_tasks().into_iter().filter(|x| filter.matches(x)).collect::<Vec<_>>();
+    assert_eq!(done.len(), 1);
+    assert_eq!(done[0].description, "Fix the build");
+    let mut tasks = context.get_all_tasks();
+    sort_tasks(&mut tasks, SortKey::Overrun);
+    assert_eq!(tasks[0].description, "Review the REPORT");
+    sort_tasks(&mut tasks, SortKey::Estimate);
+    assert_eq!(tasks[0].description, "Fix the build");
+}
+
+#[test]
+fn test_tags_and_projects() {
+    let mut task = Task::new("Fix the API", 2, vec!("code".to_string(), " code ".to_string()));
+    assert_eq!(task.tags(), ["code"]);
+    task.set_project("/work/clientA/api/");
+    assert!(task.is_in_project("work/clientA"));
+    assert!(!task.is_in_project("work/client"));
+    assert_eq!(task.project_hierarchy(), ["work/clientA/api", "work/clientA", "work"]);
+    task.add_tag("urgent");
+    let parsed = Task::from_record(&task.to_record()).unwrap();
+    assert_eq!(parsed.tags(), ["code", "urgent"]);
+    assert_eq!(parsed.project().unwrap(), "work/clientA/api");
+    // The single kind of older files becomes the first tag.
+    let mut record = task.to_record();
+    record[6] = "review".to_string();
+    record.truncate(15);
+    let parsed = Task::from_record(&record).unwrap();
+    assert_eq!(parsed.tags(), ["review"]);
+    assert_eq!(parsed.project(), None);
+}
+
+#[test]
+fn test_short_ids() {
+    let mut context = Context::default();
+    context.add_task(Task::new("First", 1, vec!()));
+    context.add_task(Task::new("Second", 1, vec!()));
+    context.tasks.push(Task::new("Legacy", 1, vec!()));
+    context.number_tasks();
+    assert_eq!(context.tasks.iter().map(|x| x.id()).collect::<Vec<u32>>(), [1, 2, 3]);
+    assert_eq!(context.find_task("2").unwrap().description, "Second");
+    let parsed = Task::from_record(&context.tasks[1].to_record()).unwrap();
+    assert_eq!(parsed.id(), 2);
+    let uuid_begin = context.tasks[2].uuid.to_string()[..8].to_string();
+    assert_eq!(context.find_task(&uuid_begin).unwrap().description, "Legacy");
+    match context.find_task("") {
+        Err(Error::Identification(_, IdentificationError::AmbiguousMatch(c))) =>
+            assert_eq!(c.len(), 3),
+        _ => panic!("an empty identifier should match every task")
+    }
+}
+
+#[test]
+fn test_remove_and_archive() {
+    let mut context = Context::default();
+    context.add_task(Task::new("Done long ago", 1, vec!()));
+    context.add_task(Task::new("Done today", 1, vec!()));
+    context.add_task(Task::new("Ongoing", 1, vec!()));
+    context.add_task(Task::new("Open", 1, vec!()));
+    context.tasks[0].end_date = Some(chrono::Local::now() - chrono::Duration::days(40));
+    context.tasks[1].finish();
+    context.tasks[2].is_ongoing = true;
+    let archived = context.take_finished_tasks(Some(chrono::Local::now()
+                                                    - chrono::Duration::days(30)));
+    assert_eq!(archived.len(), 1);
+    assert_eq!(archived[0].description, "Done long ago");
+    assert_eq!(context.take_finished_tasks(None).len(), 1);
+    assert!(context.remove_task("3").is_err());
+    assert_eq!(context.remove_task("4").unwrap().description, "Open");
+    assert_eq!(context.tasks.len(), 1);
+}
+
+#[test]
+fn test_reopen() {
+    let mut context = Context::default();
+    context.add_task(Task::new("Fix the bug", 1, vec!()));
+    assert!(!context.tasks[0].reopen());
+    context.tasks[0].finish();
+    assert!(context.get_current_tasks().is_empty());
+    assert!(context.tasks[0].reopen());
+    assert_eq!(context.get_current_tasks().len(), 1);
+    let parsed = Task::from_record(&context.tasks[0].to_record()).unwrap();
+    assert!(!parsed.is_finished());
+    assert_eq!(parsed.previous_end_dates().len(), 1);
+}
+
+#[test]
+fn test_pause_timer() {
+    let mut context = Context::default();
+    assert!(context.pause_timer().is_err());
+    context.timer = Some(chrono::Local::now() - chrono::Duration::minutes(10));
+    context.timer_length = Some(25);
+    assert_eq!(context.pause_timer().unwrap(), true);
+    assert_eq!(context.pause_timer().unwrap(), false);
+    context.paused_at = Some(chrono::Local::now() - chrono::Duration::minutes(5));
+    // Time left does not run while paused.
+    let remaining = context.remaining().unwrap().num_seconds();
+    assert!(remaining == 1200 || remaining == 1199);
+    assert!(context.resume_timer());
+    assert!(!context.resume_timer());
+    assert_eq!(context.paused_seconds, 300);
+    let parsed = Context::from_record(&context.metadata_to_record()).unwrap();
+    assert_eq!(parsed.paused_seconds, 300);
+    assert!(parsed.paused_at.is_none());
+}
+
+#[test]
+fn test_recover_stale_timer() {
+    let mut context = Context::default();
+    context.add_task(Task::new("Survive a reboot", 2, vec!()));
+    assert!(!context.has_stale_timer(false));
+    context.tasks[0].before_starting_pomodoro();
+    // An ongoing task without any timer can never be stopped.
+    assert!(context.has_stale_timer(true));
+    context.timer = Some(chrono::Local::now() - chrono::Duration::minutes(10));
+    context.timer_length = Some(25);
+    assert!(!context.has_stale_timer(true));
+    assert!(context.has_stale_timer(false));
+    context.timer = Some(chrono::Local::now() - chrono::Duration::minutes(40));
+    assert!(context.has_stale_timer(true));
+    let entry = context.recover(true).unwrap();
+    assert_eq!(entry.outcome, Outcome::Completed);
+    assert_eq!((entry.end - entry.start).num_minutes(), 25);
+    assert_eq!(context.tasks[0].pomodori_count, 1);
+    assert!(!context.has_ongoing_task() && context.timer.is_none());
+    assert!(context.recover(false).is_none());
+}
@@ -0,0 +1,255 @@
+//! Output of `list`, `show` and `status`, for humans or for scripts
+//! (status bars, editor integrations...).
+//!
+//! The JSON and TSV schemas are stable: fields may be added at the
+//! end, but never removed, renamed or reordered. Dates are RFC 3339,
+//! days are YYYY-MM-DD; missing values are `null` in JSON and empty
+//! in TSV. In TSV, tabs, newlines and backslashes inside a field are
+//! escaped as `\t`, `\n` and `\\`, and the first line is a header.
+//!
+//! A task has the following fields:
+//!
+//! - `uuid`
+//! - `description`
+//! - `kind`: the first tag, from the time tasks had a single kind
+//! - `state`: `open`, `ongoing` or `done`
+//! - `pomodori_count`: pomodori done on the task
+//! - `pomodori_estimate`
+//! - `pomodori_voided`: pomodori stopped before their end
+//! - `start_date`, `end_date`
+//! - `planned_for`: day of the "To Do Today" sheet the task is on
+//! - `unplanned`: when the task was jotted down as unplanned
+//! - `interruptions`: in JSON, a list of `{kind, date, note}` objects,
+//!   kind being `internal` or `external`; in TSV, marks such as `''-`
+//! - `notes` (JSON only): a list of `{date, text}` objects
+//! - `tags`: in JSON, a list of strings; in TSV, separated by commas
+//! - `project`: e.g. `work/clientA/api`
+//! - `id`: short id of the task
+//! - `previous_end_dates`: when the task was finished before being
+//!   reopened; in TSV, separated by commas
+//!
+//! The status has the following fields:
+//!
+//! - `phase`: `idle`, `work`, `short_break` or `long_break`
+//! - `task`: uuid of the ongoing task
+//! - `description`: description of the ongoing task
+//! - `started`: when the current pomodoro or pause started
+//! - `remaining_seconds`: time left in the current pomodoro or pause
+//! - `cycle_position`: pomodori done in the current cycle
+//! - `cycle_length`: pomodori in a full cycle, before a long pause
+//! - `paused`: is the timer paused (`true` or `false`)
+//! - `paused_seconds`: time the current pomodoro or pause spent paused
+//!
+//! `status --short` displays a single line built from a template
+//! (`status_template` in the config file), with these placeholders:
+//!
+//! - `{icon}`: a symbol for the phase, or for a paused timer
+//! - `{phase}`, `{task}`: name of the phase, description of the task
+//! - `{remaining}` or `{remaining:mm:ss}`: time left, e.g. `12:05`
+//! - `{remaining:m}`: time left, in minutes
+//! - `{cycle}`, `{cycle_length}`: `cycle_position` and `cycle_length`
+use std::str::FromStr;
+use pomorust::model::{Context, Phase, Task};
+use pomorust::utils::{json_maybe_date, json_maybe_string, tsv_field};
+
+pub const TASK_TSV_HEADER: &'static str = "uuid\tdescription\tkind\tstate\tpomodori_count\t\
+    pomodori_estimate\tpomodori_voided\tstart_date\tend_date\tplanned_for\tunplanned\t\
+    interruptions\ttags\tproject\tid\tprevious_end_dates";
+
+pub const DEFAULT_STATUS_TEMPLATE: &'static str =
+    "{icon} {task} {remaining:mm:ss} [{cycle}/{cycle_length}]";
+
+pub const STATUS_TSV_HEADER: &'static str = "phase\ttask\tdescription\tstarted\t\
+    remaining_seconds\tcycle_position\tcycle_length\tpaused\tpaused_seconds";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Plain,
+    Json,
+    Tsv
+}
+
+impl FromStr for OutputFormat {
+    type Err = ();
+    fn from_str(src: &str) -> Result<OutputFormat, ()> {
+        match src {
+            "plain" => Ok(OutputFormat::Plain),
+            "json" => Ok(OutputFormat::Json),
+            "tsv" => Ok(OutputFormat::Tsv),
+            _ => Err(())
+        }
+    }
+}
+
+/// Display a list of tasks. Plain output is left to the caller,
+/// since listings differ in how they present tasks to humans.
+pub fn print_tasks(tasks: &[&Task], format: OutputFormat) {
+    match format {
+        OutputFormat::Plain => for t in tasks.iter() {
+            println!("{}", t.to_list_line());
+        },
+        OutputFormat::Json => println!("[{}]", tasks.iter()
+                                       .map(|x| x.to_json())
+                                       .collect::<Vec<String>>()
+                                       .join(",")),
+        OutputFormat::Tsv => {
+            println!("{}", TASK_TSV_HEADER);
+            for t in tasks.iter() {
+                println!("{}", t.to_tsv_line());
+            }
+        }
+    }
+}
+
+pub fn print_task(task: &Task, format: OutputFormat) {
+    match format {
+        OutputFormat::Plain => println!("{}", task.to_details()),
+        OutputFormat::Json => println!("{}", task.to_json()),
+        OutputFormat::Tsv => {
+            println!("{}", TASK_TSV_HEADER);
+            println!("{}", task.to_tsv_line());
+        }
+    }
+}
+
+pub fn print_status(context: &Context, format: OutputFormat) {
+    let task = context.get_ongoing_task();
+    let remaining = context.remaining().map(|x| x.num_seconds());
+    let paused_seconds = context.paused_duration().num_seconds();
+    match format {
+        OutputFormat::Plain => context.display_status(),
+        OutputFormat::Json => println!(
+            "{{\"phase\":\"{}\",\"task\":{},\"description\":{},\"started\":{},\
+             \"remaining_seconds\":{},\"cycle_position\":{},\"cycle_length\":{},\
+             \"paused\":{},\"paused_seconds\":{}}}",
+            context.phase().name(),
+            json_maybe_string(task.map(|x| x.uuid.to_string()).as_ref()),
+            json_maybe_string(task.map(|x| x.description.clone()).as_ref()),
+            json_maybe_date(context.timer),
+            remaining.map_or("null".to_string(), |x| x.to_string()),
+            context.cycle_position(), context.durations.long_break_interval,
+            context.is_paused(), paused_seconds),
+        OutputFormat::Tsv => {
+            println!("{}", STATUS_TSV_HEADER);
+            println!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                     context.phase().name(),
+                     task.map_or(String::new(), |x| x.uuid.to_string()),
+                     task.map_or(String::new(), |x| tsv_field(&x.description)),
+                     context.timer.map_or(String::new(), |x| x.to_rfc3339()),
+                     remaining.map_or(String::new(), |x| x.to_string()),
+                     context.cycle_position(), context.durations.long_break_interval,
+                     context.is_paused(), paused_seconds);
+        }
+    }
+}
+
+fn phase_icon(phase: Phase) -> &'static str {
+    match phase {
+        Phase::Idle => "\u{1f4a4}",
+        Phase::Work => "\u{1f345}",
+        Phase::ShortBreak => "\u{2615}",
+        Phase::LongBreak => "\u{1f334}"
+    }
+}
+
+/// Fill a status template with the state of the current pomodoro.
+/// Placeholders without a value are dropped, along with the spaces
+/// before them (or after them, at the start of the line).
+pub fn render_status_line(context: &Context, template: &str) -> String {
+    let phase = context.phase();
+    let task = match phase {
+        Phase::Work => context.get_ongoing_task().map_or("", |x| &x.description[..]),
+        _ => ""
+    };
+    // A late background process should not make us display negative times.
+    let remaining = context.remaining().map(|x| if x.num_seconds() < 0 { 0 } else { x.num_seconds() });
+    let clock = remaining.map_or(String::new(), |x| format!("{:02}:{:02}", x / 60, x % 60));
+    let minutes = remaining.map_or(String::new(), |x| ((x + 59) / 60).to_string());
+    let icon = match context.is_paused() {
+        true => "\u{23f8}",
+        false => phase_icon(phase)
+    };
+    let cycle = context.cycle_position().to_string();
+    let cycle_length = context.durations.long_break_interval.to_string();
+    let values = [("icon", icon), ("phase", phase.name()), ("task", task),
+                  ("remaining", &clock[..]), ("remaining:mm:ss", &clock[..]),
+                  ("remaining:m", &minutes[..]), ("cycle", &cycle[..]),
+                  ("cycle_length", &cycle_length[..])];
+    // Values are never searched for placeholders: a task may well be
+    // called "{cycle}".
+    let mut line = String::new();
+    let mut skip_blanks = false;
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let placeholder = rest[start..].find('}').and_then(|x| {
+            let name = &rest[start + 1..start + x];
+            values.iter().find(|&&(n, _)| n == name).map(|&(_, v)| (start + x + 1, v))
+        });
+        match placeholder {
+            Some((end, value)) => {
+                push_template_text(&mut line, &rest[..start], &mut skip_blanks);
+                if value.is_empty() {
+                    let len = line.trim_right().len();
+                    line.truncate(len);
+                    skip_blanks = line.is_empty();
+                } else {
+                    line.push_str(value);
+                    skip_blanks = false;
+                }
+                rest = &rest[end..];
+            },
+            None => {
+                push_template_text(&mut line, &rest[..start + 1], &mut skip_blanks);
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    push_template_text(&mut line, rest, &mut skip_blanks);
+    line
+}
+
+/// Add text of the template to the line, dropping its leading blanks
+/// if they followed an empty placeholder.
+fn push_template_text(line: &mut String, text: &str, skip_blanks: &mut bool) {
+    let text = match *skip_blanks {
+        true => text.trim_left(),
+        false => text
+    };
+    *skip_blanks = *skip_blanks && text.is_empty();
+    line.push_str(text);
+}
+
+#[test]
+fn test_render_status_line() {
+    use chrono;
+    let mut context = Context::default();
+    context.add_task(Task::new("Write the report", 2, vec!()));
+    assert_eq!(render_status_line(&context, "{phase} {task} {remaining}"), "idle");
+    context.tasks[0].before_starting_pomodoro();
+    context.timer = Some(chrono::Local::now() - chrono::Duration::seconds(90));
+    context.timer_length = Some(25);
+    let line = render_status_line(&context, "{phase}: {task} {remaining:mm:ss} ({remaining:m})");
+    // Leave a second of slack for slow test runs.
+    assert!(line == "work: Write the report 23:30 (24)" || line == "work: Write the report 23:29 (24)");
+    // A timer without any ongoing task should not make status fail.
+    context.tasks[0].is_ongoing = false;
+    assert_eq!(render_status_line(&context, "{phase} {task}"), "work");
+    // Time left is frozen while paused.
+    context.paused_at = Some(chrono::Local::now() - chrono::Duration::seconds(60));
+    context.paused_seconds = 30;
+    assert_eq!(render_status_line(&context, "{icon} {remaining:m}"), "\u{23f8} 25");
+}
+
+#[test]
+fn test_status_line_placeholders_in_values() {
+    use chrono;
+    let mut context = Context::default();
+    context.add_task(Task::new("Count {cycle} and {remaining}", 2, vec!()));
+    context.tasks[0].before_starting_pomodoro();
+    context.timer = Some(chrono::Local::now());
+    assert_eq!(render_status_line(&context, "{task}  |  {cycle}/{cycle_length}"),
+               "Count {cycle} and {remaining}  |  0/4");
+    assert_eq!(render_status_line(&context, "{unknown} {phase}"), "{unknown} work");
+    context.tasks[0].is_ongoing = false;
+    assert_eq!(render_status_line(&context, "{task}  {phase}  ({cycle}) {task}"), "work  (0)");
+}
@@ -0,0 +1,82 @@
+//! Records are lists of text fields, as stored in the task, history
+//! and archive files.
+use std::mem;
+
+/// A record and the line it starts on.
+pub type Record = (usize, Vec<String>);
+
+/// Fields are separated by semicolons, records by newlines.
+/// Fields containing a separator or a double quote are enclosed
+/// in double quotes, double quotes being doubled (as in RFC 4180).
+fn escape_field(field: &str) -> String {
+    if field.chars().any(|c| c == ';' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", field.replace("\"", "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+pub fn serialize_record(fields: &[String]) -> String {
+    let escaped = fields.iter().map(|x| escape_field(x)).collect::<Vec<String>>();
+    format!("{}\n", escaped.join(";"))
+}
+
+pub fn parse_records(text: &str) -> Vec<Record> {
+    let mut records = vec!();
+    let mut record = vec!();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut line = 1;
+    let mut record_line = 1;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\n' {
+            line += 1;
+        }
+        if in_quotes {
+            if c != '"' {
+                field.push(c);
+            } else if chars.peek() == Some(&'"') {
+                field.push('"');
+                chars.next();
+            } else {
+                in_quotes = false;
+            }
+            continue;
+        }
+        match c {
+            '"' => in_quotes = true,
+            ';' => record.push(mem::replace(&mut field, String::new())),
+            '\r' => {},
+            '\n' => {
+                record.push(mem::replace(&mut field, String::new()));
+                records.push((record_line, mem::replace(&mut record, vec!())));
+                record_line = line;
+            },
+            _ => field.push(c)
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push((record_line, record));
+    }
+    // Blank lines are not records.
+    records.into_iter().filter(|&(_, ref x)| x.len() > 1 || x[0].len() > 0).collect()
+}
+
+#[test]
+fn test_hostile_fields_round_trip() {
+    let fields = vec!("plain".to_string(),
+                      "semi;colon".to_string(),
+                      "\"quoted\" and \"\"doubled\"\"".to_string(),
+                      "multi\nline\r\nfield".to_string(),
+                      "".to_string(),
+                      ";\";\n".to_string());
+    let text = format!("{}{}", serialize_record(&fields), serialize_record(&fields));
+    let records = parse_records(&text);
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].1, fields);
+    assert_eq!(records[1].1, fields);
+    // The second record starts after the three newlines of the first one.
+    assert_eq!(records[1].0, 5);
+}
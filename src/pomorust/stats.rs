@@ -0,0 +1,264 @@
+use std::collections::{BTreeMap, BTreeSet};
+use chrono;
+use chrono::{Datelike, NaiveDate};
+use pomorust::history::{LogEntry, Outcome};
+use pomorust::model::Task;
+
+/// How many recently finished tasks with a tag are considered
+/// when judging estimates for this tag.
+const ROLLING_WINDOW: usize = 10;
+
+/// How estimates compare to the pomodori actually spent,
+/// on finished tasks that had an estimate.
+#[derive(Debug, Default)]
+pub struct Accuracy {
+    pub tasks: u16,
+    pub estimated: u32,
+    pub actual: u32,
+    /// Tasks that took more pomodori than estimated
+    pub over: u16,
+    /// Tasks that took less pomodori than estimated
+    pub under: u16,
+    pub exact: u16
+}
+
+impl Accuracy {
+    pub fn add(&mut self, estimated: u16, actual: u16) {
+        self.tasks += 1;
+        self.estimated += estimated as u32;
+        self.actual += actual as u32;
+        if actual > estimated {
+            self.over += 1;
+        } else if actual < estimated {
+            self.under += 1;
+        } else {
+            self.exact += 1;
+        }
+    }
+
+    /// Actual pomodori spent for an estimated one.
+    pub fn ratio(&self) -> Option<f32> {
+        match self.estimated {
+            0 => None,
+            e => Some(self.actual as f32 / e as f32)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Report {
+    /// Completed pomodori for each day
+    pub per_day: BTreeMap<NaiveDate, u16>,
+    /// Unplanned tasks jotted down each day
+    pub unplanned_per_day: BTreeMap<NaiveDate, u16>,
+    /// Completed pomodori for each ISO week (year, week)
+    pub per_week: BTreeMap<(i32, u32), u16>,
+    /// Pomodori spent on tasks with each tag
+    pub per_tag: BTreeMap<String, u16>,
+    /// Pomodori spent on each project, sub-projects included
+    pub per_project: BTreeMap<String, u16>,
+    pub accuracy: Accuracy,
+    pub finished_tasks: u16,
+    /// Pomodori spent on finished tasks
+    pub finished_pomodori: u32,
+    /// Days in a row, up to today, with at least one pomodoro
+    pub current_streak: u16,
+    pub longest_streak: u16
+}
+
+impl Report {
+    /// Compute statistics on the pomodori and tasks between two days
+    /// (inclusive). Tasks are only considered once finished.
+    pub fn new(tasks: &[&Task], history: &[LogEntry],
+               since: Option<NaiveDate>, until: Option<NaiveDate>) -> Report {
+        let mut per_day = BTreeMap::new();
+        let mut per_week = BTreeMap::new();
+        for entry in history.iter() {
+            if entry.outcome != Outcome::Completed || !entry.matches(since, until, None) {
+                continue;
+            }
+            let day = entry.start.naive_local().date();
+            let week = day.iso_week();
+            *per_day.entry(day).or_insert(0) += 1;
+            *per_week.entry((week.year(), week.week())).or_insert(0) += 1;
+        }
+
+        let mut unplanned_per_day = BTreeMap::new();
+        for task in tasks.iter() {
+            if let Some(d) = task.unplanned_date() {
+                let day = d.naive_local().date();
+                if since.map_or(true, |x| day >= x) && until.map_or(true, |x| day <= x) {
+                    *unplanned_per_day.entry(day).or_insert(0) += 1;
+                }
+            }
+        }
+
+        let mut per_tag = BTreeMap::new();
+        let mut per_project = BTreeMap::new();
+        let mut accuracy = Accuracy::default();
+        let mut finished_tasks = 0;
+        let mut finished_pomodori = 0;
+        for task in tasks.iter() {
+            let end = match task.end_date() {
+                Some(d) => d.naive_local().date(),
+                None => continue
+            };
+            if since.map_or(false, |x| end < x) || until.map_or(false, |x| end > x) {
+                continue;
+            }
+            finished_tasks += 1;
+            finished_pomodori += task.pomodori_count() as u32;
+            if task.tags().is_empty() {
+                *per_tag.entry("(none)".to_string()).or_insert(0) += task.pomodori_count();
+            }
+            for tag in task.tags() {
+                *per_tag.entry(tag.clone()).or_insert(0) += task.pomodori_count();
+            }
+            for project in task.project_hierarchy() {
+                *per_project.entry(project).or_insert(0) += task.pomodori_count();
+            }
+            if task.pomodori_estimate() > 0 {
+                accuracy.add(task.pomodori_estimate(), task.pomodori_count());
+            }
+        }
+
+        let (current_streak, longest_streak) = streaks(&per_day,
+                                                       chrono::Local::now().naive_local().date());
+        Report { per_day: per_day,
+                 unplanned_per_day: unplanned_per_day,
+                 per_week: per_week,
+                 per_tag: per_tag,
+                 per_project: per_project,
+                 accuracy: accuracy,
+                 finished_tasks: finished_tasks,
+                 finished_pomodori: finished_pomodori,
+                 current_streak: current_streak,
+                 longest_streak: longest_streak }
+    }
+
+    pub fn display(&self) {
+        println!("Pomodori per day");
+        let days = self.per_day.keys().chain(self.unplanned_per_day.keys())
+            .collect::<BTreeSet<&NaiveDate>>();
+        for day in days {
+            let count = self.per_day.get(day).map_or(0, |x| *x);
+            match self.unplanned_per_day.get(day) {
+                Some(u) => println!("  {}  {} ({} unplanned)", day.format("%Y-%m-%d %a"), count, u),
+                None => println!("  {}  {}", day.format("%Y-%m-%d %a"), count)
+            }
+        }
+        println!("Pomodori per week");
+        for (&(year, week), count) in self.per_week.iter() {
+            println!("  {}-W{:02}  {}", year, week, count);
+        }
+        println!("Pomodori per tag (finished tasks)");
+        for (tag, count) in self.per_tag.iter() {
+            println!("  {: <15} {}", tag, count);
+        }
+        if !self.per_project.is_empty() {
+            println!("Pomodori per project (finished tasks, sub-projects included)");
+            for (project, count) in self.per_project.iter() {
+                println!("  {: <30} {}", project, count);
+            }
+        }
+        println!("Estimates ({} finished tasks with an estimate)", self.accuracy.tasks);
+        match self.accuracy.ratio() {
+            Some(r) => println!("  estimated {}, actual {} ({:.0}%)",
+                                self.accuracy.estimated, self.accuracy.actual, r * 100.0),
+            None => println!("  no estimate to compare to")
+        }
+        println!("  over {}, under {}, exact {}",
+                 self.accuracy.over, self.accuracy.under, self.accuracy.exact);
+        if self.finished_tasks > 0 {
+            println!("{:.1} pomodori per finished task",
+                     self.finished_pomodori as f32 / self.finished_tasks as f32);
+        }
+        println!("Streak : {} days (longest {})", self.current_streak, self.longest_streak);
+    }
+}
+
+/// The last finished tasks with a given tag that had an estimate,
+/// most recent first.
+fn recent_tasks_with_tag<'a>(tasks: &[&'a Task], tag: &str) -> Vec<&'a Task> {
+    let mut finished = tasks.iter()
+        .filter(|x| x.is_finished() && x.pomodori_estimate() > 0)
+        .filter(|x| x.has_tag(tag))
+        .map(|x| *x)
+        .collect::<Vec<&Task>>();
+    finished.sort_by(|a, b| b.end_date().cmp(&a.end_date()));
+    finished.truncate(ROLLING_WINDOW);
+    finished
+}
+
+/// Accuracy of the estimates on the last tasks with a tag, archived
+/// tasks included.
+pub fn tag_accuracy(tasks: &[&Task], tag: &str) -> Accuracy {
+    let mut accuracy = Accuracy::default();
+    for task in recent_tasks_with_tag(tasks, tag) {
+        accuracy.add(task.pomodori_estimate(), task.pomodori_count());
+    }
+    accuracy
+}
+
+/// Pomodori actually spent, on average, on the last tasks with a tag.
+pub fn suggested_estimate(tasks: &[&Task], tag: &str) -> Option<u16> {
+    let accuracy = tag_accuracy(tasks, tag);
+    match accuracy.tasks {
+        0 => None,
+        n => Some((accuracy.actual as f32 / n as f32).round() as u16)
+    }
+}
+
+/// Count days in a row with pomodori: the streak still running
+/// (it may end yesterday, today is not over) and the longest one.
+fn streaks(per_day: &BTreeMap<NaiveDate, u16>, today: NaiveDate) -> (u16, u16) {
+    let mut longest = 0;
+    let mut running = 0;
+    let mut previous: Option<NaiveDate> = None;
+    for day in per_day.keys() {
+        running = match previous {
+            Some(p) if p.succ() == *day => running + 1,
+            _ => 1
+        };
+        if running > longest {
+            longest = running;
+        }
+        previous = Some(*day);
+    }
+    let current = match previous {
+        Some(p) if p == today || p.succ() == today => running,
+        _ => 0
+    };
+    (current, longest)
+}
+
+#[test]
+fn test_streaks() {
+    let today = NaiveDate::from_ymd(2016, 3, 10);
+    let mut per_day = BTreeMap::new();
+    for d in vec!(1, 2, 3, 4, 7, 8, 9) {
+        per_day.insert(NaiveDate::from_ymd(2016, 3, d), 1);
+    }
+    assert_eq!(streaks(&per_day, today), (3, 4));
+    per_day.remove(&NaiveDate::from_ymd(2016, 3, 9));
+    assert_eq!(streaks(&per_day, today), (0, 4));
+}
+
+#[test]
+fn test_suggested_estimate() {
+    let mut tasks = vec!();
+    for &(estimate, actual) in [(2, 3), (2, 5), (4, 4)].iter() {
+        let mut task = Task::new("Review", estimate, vec!("review".to_string()));
+        for _ in 0..actual {
+            task.after_doing_pomodoro();
+        }
+        task.finish();
+        tasks.push(task);
+    }
+    tasks.push(Task::new("Unfinished review", 1, vec!("review".to_string())));
+    let tasks = tasks.iter().collect::<Vec<&Task>>();
+    assert_eq!(suggested_estimate(&tasks, "review"), Some(4));
+    assert_eq!(suggested_estimate(&tasks, "support"), None);
+    let accuracy = tag_accuracy(&tasks, "review");
+    assert_eq!((accuracy.over, accuracy.under, accuracy.exact), (2, 0, 1));
+}
@@ -0,0 +1,412 @@
+//! `pomorust tui`: a full screen view of the timer, of the tasks of the
+//! day and of the session, refreshed every second.
+//!
+//! Keys:
+//!
+//! - up/down or k/j: select a task
+//! - tab: switch between the "To Do Today" sheet and every open task
+//! - s or enter: start a pomodoro on the selected task
+//! - d: mark the selected task as done
+//! - m: change the description of the selected task
+//! - + and -: change the estimate of the selected task
+//! - x: void the pomodoro, or end the break
+//! - p: pause or resume the timer
+//! - q: quit
+use std::cmp;
+use std::collections::VecDeque;
+use std::io;
+use std::io::{Read, Write};
+use std::mem;
+use std::ptr;
+use std::str;
+use chrono;
+use libc;
+use pomorust::config;
+use pomorust::daemon;
+use pomorust::daemon::Request;
+use pomorust::error::Error;
+use pomorust::history::Outcome;
+use pomorust::model::{Context, Task, TaskModification};
+use pomorust::output;
+use pomorust::stats::Report;
+
+/// How often the screen is refreshed when no key is pressed.
+const REFRESH_MS: i32 = 1000;
+/// Lines of the screen not used by the listing of tasks.
+const FIXED_LINES: usize = 13;
+
+#[derive(Debug, PartialEq)]
+enum Key {
+    Up,
+    Down,
+    Enter,
+    Tab,
+    Escape,
+    Backspace,
+    /// Ctrl-C or Ctrl-D
+    Interrupt,
+    Char(char)
+}
+
+/// Keys read at once, e.g. when text is pasted. Unknown escape
+/// sequences and control characters are dropped.
+fn parse_keys(bytes: &[u8]) -> Vec<Key> {
+    let mut keys = vec!();
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        let (key, length) = if rest.starts_with(b"\x1b[A") || rest.starts_with(b"\x1bOA") {
+            (Some(Key::Up), 3)
+        } else if rest.starts_with(b"\x1b[B") || rest.starts_with(b"\x1bOB") {
+            (Some(Key::Down), 3)
+        } else if rest.starts_with(b"\x1b[") || rest.starts_with(b"\x1bO") {
+            // Skip the whole of other sequences, e.g. "\x1b[5~".
+            (None, rest.iter().skip(2).position(|x| x.is_ascii_alphabetic() || *x == b'~')
+                .map_or(rest.len(), |x| x + 3))
+        } else {
+            match rest[0] {
+                b'\r' | b'\n' => (Some(Key::Enter), 1),
+                b'\t' => (Some(Key::Tab), 1),
+                0x1b => (Some(Key::Escape), 1),
+                0x7f | 0x08 => (Some(Key::Backspace), 1),
+                0x03 | 0x04 => (Some(Key::Interrupt), 1),
+                _ => {
+                    // A UTF-8 character takes up to 4 bytes.
+                    let length = (1..cmp::min(rest.len(), 4) + 1)
+                        .find(|x| str::from_utf8(&rest[..*x]).is_ok()).unwrap_or(1);
+                    let key = str::from_utf8(&rest[..length]).ok()
+                        .and_then(|x| x.chars().next())
+                        .and_then(|x| if x.is_control() { None } else { Some(Key::Char(x)) });
+                    (key, length)
+                }
+            }
+        };
+        keys.extend(key);
+        rest = &rest[length..];
+    }
+    keys
+}
+
+/// The terminal, switched to reading keys as soon as they are pressed,
+/// on a screen of its own. It is put back as it was when dropped.
+struct Terminal {
+    original: libc::termios,
+    /// What was done on SIGWINCH before
+    original_resize: libc::sigaction,
+    /// Keys read but not handled yet
+    pending: VecDeque<Key>
+}
+
+/// Receiving the signal is enough: it interrupts the wait for a key.
+extern "C" fn on_resize(_: libc::c_int) {}
+
+impl Terminal {
+    fn new() -> Result<Terminal, Error> {
+        let mut original: libc::termios = unsafe { mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+            return Err(Error::InvalidCommand("the tui needs a terminal".to_string()));
+        }
+        let mut raw = original;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+            return Err(Error::Io(io::Error::last_os_error()));
+        }
+        // Without SA_RESTART, so that a resize interrupts `poll`.
+        let mut resize: libc::sigaction = unsafe { mem::zeroed() };
+        resize.sa_sigaction = on_resize as *const () as libc::sighandler_t;
+        let mut original_resize: libc::sigaction = unsafe { mem::zeroed() };
+        unsafe { libc::sigaction(libc::SIGWINCH, &resize, &mut original_resize) };
+        print!("\x1b[?1049h\x1b[?25l");
+        try!(io::stdout().flush());
+        Ok(Terminal { original: original, original_resize: original_resize,
+                      pending: VecDeque::new() })
+    }
+
+    /// Columns and lines of the terminal.
+    fn size(&self) -> (usize, usize) {
+        let mut size: libc::winsize = unsafe { mem::zeroed() };
+        match unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } {
+            0 if size.ws_col > 0 && size.ws_row > 0 => (size.ws_col as usize, size.ws_row as usize),
+            _ => (80, 24)
+        }
+    }
+
+    /// Wait for a key, at most `timeout` milliseconds (-1 for ever).
+    fn read_key(&mut self, timeout: i32) -> Result<Option<Key>, Error> {
+        if let Some(key) = self.pending.pop_front() {
+            return Ok(Some(key));
+        }
+        let mut fd = libc::pollfd { fd: libc::STDIN_FILENO, events: libc::POLLIN, revents: 0 };
+        match unsafe { libc::poll(&mut fd, 1, timeout) } {
+            0 => return Ok(None),
+            n if n < 0 => {
+                let err = io::Error::last_os_error();
+                // A resized terminal interrupts the wait: just redraw.
+                return match err.kind() {
+                    io::ErrorKind::Interrupted => Ok(None),
+                    _ => Err(Error::Io(err))
+                };
+            },
+            _ => ()
+        }
+        let mut bytes = [0; 256];
+        let read = try!(io::stdin().read(&mut bytes));
+        if read == 0 {
+            return Ok(Some(Key::Interrupt));
+        }
+        self.pending.extend(parse_keys(&bytes[..read]));
+        Ok(self.pending.pop_front())
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original);
+            libc::sigaction(libc::SIGWINCH, &self.original_resize, ptr::null_mut());
+        }
+        print!("\x1b[?25h\x1b[?1049l");
+        let _ = io::stdout().flush();
+    }
+}
+
+/// `[#####-----]`, filled up to a fraction between 0 and 1.
+fn progress_bar(fraction: f32, width: usize) -> String {
+    let fraction = if fraction < 0.0 { 0.0 } else if fraction > 1.0 { 1.0 } else { fraction };
+    let filled = (fraction * width as f32).round() as usize;
+    format!("[{}{}]", "#".repeat(filled), "-".repeat(width - filled))
+}
+
+fn truncate(line: &str, width: usize) -> String {
+    line.chars().take(width).collect()
+}
+
+/// The tasks and the timer, as the other commands see them.
+fn load_context() -> Result<Context, Error> {
+    let mut context = {
+        let _lock = try!(config::lock_task_file());
+        try!(daemon::create_context())
+    };
+    try!(daemon::sync_timer(&mut context));
+    Ok(context)
+}
+
+/// Change a task as `modify` or `done` would, keeping `undo` working.
+fn change_task<F>(identifier: &str, change: F) -> Result<String, Error>
+    where F: FnOnce(&mut Task) -> String {
+    let _lock = try!(config::lock_task_file());
+    let mut context = try!(daemon::create_context());
+    let undo_point = try!(config::undo_point());
+    let message = change(try!(context.get_task(identifier)));
+    try!(config::write_task_file(&context));
+    try!(undo_point.save());
+    Ok(message)
+}
+
+struct Tui {
+    terminal: Terminal,
+    /// List every open task rather than the "To Do Today" sheet
+    all_tasks: bool,
+    selected: usize,
+    /// Outcome of the last action
+    message: String
+}
+
+impl Tui {
+    fn tasks<'a>(&self, context: &'a Context) -> Vec<&'a Task> {
+        match self.all_tasks {
+            true => context.get_current_tasks(),
+            false => context.get_today_tasks(chrono::Local::now().naive_local().date())
+        }
+    }
+
+    fn selected_task<'a>(&self, context: &'a Context) -> Result<&'a Task, Error> {
+        self.tasks(context).get(self.selected).map(|x| *x)
+            .ok_or(Error::InvalidCommand("no task selected".to_string()))
+    }
+
+    fn draw(&mut self, context: &Context) -> Result<(), Error> {
+        let (width, height) = self.terminal.size();
+        let mut lines = vec!(format!("Pomorust {}", chrono::Local::now().format("%H:%M")), String::new());
+
+        lines.push(output::render_status_line(context, "{icon} {phase} {task}"));
+        let state = match context.is_paused() {
+            true => "paused",
+            false => "left"
+        };
+        match (context.remaining(), context.timer_length) {
+            (Some(r), Some(l)) => {
+                let length = l as f32 * 60.0;
+                let bar_width = cmp::max(width as isize - 30, 10) as usize;
+                lines.push(format!("{} {} {} [{}/{}]",
+                                   progress_bar((length - r.num_seconds() as f32) / length,
+                                                bar_width),
+                                   output::render_status_line(context, "{remaining:mm:ss}"),
+                                   state, context.cycle_position(),
+                                   context.durations.long_break_interval));
+            },
+            _ => lines.push(String::new())
+        }
+        lines.push(String::new());
+
+        let tasks = self.tasks(context);
+        if self.selected >= tasks.len() {
+            self.selected = if tasks.is_empty() { 0 } else { tasks.len() - 1 };
+        }
+        lines.push(match self.all_tasks {
+            true => "Open tasks".to_string(),
+            false => "To Do Today".to_string()
+        });
+        let room = cmp::max(height as isize - FIXED_LINES as isize, 1) as usize;
+        let first = if self.selected >= room { self.selected + 1 - room } else { 0 };
+        for (i, task) in tasks.iter().enumerate().skip(first).take(room) {
+            let marker = if i == self.selected { "> " } else { "  " };
+            lines.push(truncate(&format!("{}{}", marker, task.to_list_line()), width));
+        }
+        if tasks.is_empty() {
+            lines.push("  Nothing planned : press tab to see every open task.".to_string());
+        }
+        lines.push(String::new());
+
+        lines.push(try!(self.session_summary(context)));
+        lines.push(String::new());
+        lines.push(truncate("s start  d done  m modify  +/- estimate  x stop  p pause  \
+                             tab today/all  q quit", width));
+        lines.push(truncate(&self.message, width));
+
+        print!("\x1b[H\x1b[2J{}", lines.join("\r\n"));
+        try!(io::stdout().flush());
+        Ok(())
+    }
+
+    fn session_summary(&self, context: &Context) -> Result<String, Error> {
+        let today = chrono::Local::now().naive_local().date();
+        let history = try!(config::read_history());
+        // Tasks done today may already be archived.
+        let archive = try!(config::read_archive());
+        let tasks = archive.iter().chain(context.tasks.iter()).collect::<Vec<&Task>>();
+        let report = Report::new(&tasks, &history, Some(today), Some(today));
+        let voided = history.iter()
+            .filter(|x| x.outcome == Outcome::Interrupted && x.matches(Some(today), Some(today), None))
+            .count();
+        Ok(format!("Today : {} pomodori, {} voided, {} tasks done. Streak : {} days.",
+                   report.per_day.get(&today).map_or(0, |x| *x), voided,
+                   report.finished_tasks, report.current_streak))
+    }
+
+    /// Read a line of text at the bottom of the screen; None if cancelled.
+    fn prompt(&mut self, context: &Context, question: &str,
+              initial: &str) -> Result<Option<String>, Error> {
+        let mut answer = initial.to_string();
+        loop {
+            self.message = format!("{}{}_", question, answer);
+            try!(self.draw(context));
+            match try!(self.terminal.read_key(-1)) {
+                Some(Key::Enter) => return Ok(Some(answer)),
+                Some(Key::Escape) | Some(Key::Interrupt) => return Ok(None),
+                Some(Key::Backspace) => { answer.pop(); },
+                Some(Key::Char(c)) => answer.push(c),
+                _ => ()
+            }
+        }
+    }
+
+    /// Act upon a key; return what to tell the user.
+    fn act(&mut self, key: Key, context: &Context) -> Result<String, Error> {
+        match key {
+            Key::Up | Key::Char('k') => {
+                self.selected = self.selected.saturating_sub(1);
+                Ok(String::new())
+            },
+            Key::Down | Key::Char('j') => {
+                self.selected += 1;
+                Ok(String::new())
+            },
+            Key::Tab => {
+                self.all_tasks = !self.all_tasks;
+                self.selected = 0;
+                Ok(String::new())
+            },
+            Key::Enter | Key::Char('s') => {
+                let task = try!(self.selected_task(context));
+                let durations = context.durations;
+                try!(daemon::send_or_spawn(&Request::Start(task.uuid.to_string(), durations.work,
+                                                           durations.short_break,
+                                                           durations.long_break)));
+                Ok(format!("Starting task : {}", task.to_string()))
+            },
+            Key::Char('d') => {
+                let id = try!(self.selected_task(context)).uuid.to_string();
+                change_task(&id, |task| {
+                    task.finish();
+                    format!("Done in {} pomodori, estimated {}.", task.pomodori_count(),
+                            task.pomodori_estimate())
+                })
+            },
+            Key::Char('m') => {
+                let (id, description) = {
+                    let task = try!(self.selected_task(context));
+                    (task.uuid.to_string(), task.description.clone())
+                };
+                match try!(self.prompt(context, "Description : ", &description)) {
+                    Some(ref d) if !d.trim().is_empty() => change_task(&id, |task| {
+                        task.modify(TaskModification { description: Some(d.trim().to_string()),
+                                                       ..TaskModification::default() });
+                        "Task modified.".to_string()
+                    }),
+                    _ => Ok("Cancelled.".to_string())
+                }
+            },
+            Key::Char(c) if c == '+' || c == '-' => {
+                let id = try!(self.selected_task(context)).uuid.to_string();
+                change_task(&id, |task| {
+                    let estimate = match c {
+                        '+' => task.pomodori_estimate() + 1,
+                        _ => task.pomodori_estimate().saturating_sub(1)
+                    };
+                    task.modify(TaskModification { pomodoro_estimation: Some(estimate),
+                                                   ..TaskModification::default() });
+                    format!("Estimated to {} pomodori.", estimate)
+                })
+            },
+            Key::Char('x') =>
+                Ok(daemon::answer_message(&try!(daemon::stop_timer())).to_string()),
+            Key::Char('p') => {
+                let request = match context.is_paused() {
+                    true => Request::Resume,
+                    false => Request::Pause
+                };
+                let answer = try!(daemon::send(&request));
+                Ok(daemon::answer_message(answer.first().map_or("", |x| &x[..])).to_string())
+            },
+            _ => Ok(String::new())
+        }
+    }
+}
+
+pub fn run() -> Result<(), Error> {
+    let mut tui = Tui { terminal: try!(Terminal::new()), all_tasks: false, selected: 0,
+                        message: String::new() };
+    loop {
+        let context = try!(load_context());
+        try!(tui.draw(&context));
+        match try!(tui.terminal.read_key(REFRESH_MS)) {
+            None => (),
+            Some(Key::Char('q')) | Some(Key::Interrupt) => return Ok(()),
+            Some(key) => tui.message = match tui.act(key, &context) {
+                Ok(message) => message,
+                Err(e) => e.to_string()
+            }
+        }
+    }
+}
+
+#[test]
+fn test_keys_and_progress_bar() {
+    assert_eq!(parse_keys(b"\x1b[A"), [Key::Up]);
+    assert_eq!(parse_keys("\x7fé\r".as_bytes()), [Key::Backspace, Key::Char('é'), Key::Enter]);
+    assert_eq!(parse_keys(b"\x1b[5~q\x1b"), [Key::Char('q'), Key::Escape]);
+    assert_eq!(progress_bar(0.5, 10), "[#####-----]");
+    assert_eq!(progress_bar(1.5, 4), "[####]");
+}
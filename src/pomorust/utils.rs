@@ -1,32 +1,90 @@
-use std::fs::File;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::os::unix::fs::OpenOptionsExt;
 use std::env;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::process;
 use std::os::unix::process::CommandExt;
-use std::io::BufReader;
+use std::io;
+use std::io::{BufReader, Read, Write};
 use notify_rust::Notification;
 use notify_rust::NotificationHint as Hint;
 use chrono;
 use rodio;
+use libc;
+use pomorust::error::Error;
 
 pub type MaybeLocalDate = Option<chrono::DateTime<chrono::Local>>;
 
-pub fn wait_for(minutes: u16) {
-    thread::sleep(Duration::new(minutes as u64 * 60, 0));
+/// Gaps shorter than this are only a busy machine, not a suspend.
+const SUSPEND_THRESHOLD_SECONDS: i64 = 2;
+
+/// Notice when the machine was suspended: the monotonic clock stands
+/// still meanwhile, while the wall clock keeps going.
+pub struct SuspendWatch {
+    wall: chrono::DateTime<chrono::Local>,
+    monotonic: Instant
+}
+
+impl SuspendWatch {
+    pub fn new() -> SuspendWatch {
+        SuspendWatch { wall: chrono::Local::now(), monotonic: Instant::now() }
+    }
+
+    /// How long the machine was suspended since the last check.
+    pub fn check(&mut self) -> chrono::Duration {
+        let awake = self.monotonic.elapsed();
+        let awake = chrono::Duration::seconds(awake.as_secs() as i64)
+            + chrono::Duration::nanoseconds(awake.subsec_nanos() as i64);
+        let now = chrono::Local::now();
+        let suspended = now - self.wall - awake;
+        self.wall = now;
+        self.monotonic = Instant::now();
+        match suspended > chrono::Duration::seconds(SUSPEND_THRESHOLD_SECONDS) {
+            true => suspended,
+            false => chrono::Duration::zero()
+        }
+    }
 }
 
 /// Try to parse a date if a string is not empty. Fail if the date
 /// is not correct. Return None if the string is empty.
-pub fn parse_maybe_local_date(str: &str, err_str: &str) -> MaybeLocalDate {
+pub fn parse_maybe_local_date(str: &str, err_str: &str) -> Result<MaybeLocalDate, String> {
     match str.len() {
-        0 => None,
-        _ => Some(str.parse::<chrono::DateTime<chrono::Local>>()
-            .ok()
-            .expect(err_str))
+        0 => Ok(None),
+        _ => str.parse::<chrono::DateTime<chrono::Local>>()
+            .map(|x| Some(x))
+            .map_err(|_| err_str.to_string())
+    }
+}
+
+/// Parse a day given on the command line.
+pub fn parse_date(str: &str) -> Result<chrono::NaiveDate, Error> {
+    chrono::NaiveDate::parse_from_str(str, "%Y-%m-%d")
+        .map_err(|_| Error::InvalidCommand(format!("invalid date \"{}\", expected YYYY-MM-DD", str)))
+}
+
+pub fn parse_maybe_date(str: Option<String>) -> Result<Option<chrono::NaiveDate>, Error> {
+    match str {
+        Some(s) => parse_date(&s).map(|x| Some(x)),
+        None => Ok(None)
     }
 }
 
+/// Parse an age given on the command line: days ("30d", or just "30")
+/// or weeks ("4w").
+pub fn parse_age(str: &str) -> Result<chrono::Duration, Error> {
+    let (number, days) = match str.chars().last() {
+        Some('d') => (&str[..str.len() - 1], 1),
+        Some('w') => (&str[..str.len() - 1], 7),
+        _ => (str, 1)
+    };
+    number.parse::<i64>()
+        .map(|x| chrono::Duration::days(x * days))
+        .map_err(|_| Error::InvalidCommand(format!("invalid age \"{}\", expected e.g. 30d or 4w", str)))
+}
+
 pub fn parse_maybe_string(str: &str) -> Option<String> {
     match str.len() {
         0 => None,
@@ -34,43 +92,189 @@ pub fn parse_maybe_string(str: &str) -> Option<String> {
     }
 }
 
-pub fn ding() {
-    let endpoint = rodio::get_default_endpoint().unwrap();
+pub fn ding() -> Result<(), Error> {
+    let endpoint = try!(rodio::get_default_endpoint()
+        .ok_or(Error::Audio("no audio output available".to_string())));
     let sink = rodio::Sink::new(&endpoint);
     let path = concat!(env!("CARGO_MANIFEST_DIR"),
             "/data/ding.ogg");
-    let file = File::open(path).unwrap();
-    sink.append(rodio::Decoder::new(BufReader::new(file)).unwrap());
+    let file = try!(File::open(path).map_err(|e| Error::Audio(e.to_string())));
+    let decoder = try!(rodio::Decoder::new(BufReader::new(file))
+        .map_err(|_| Error::Audio("could not decode sound file".to_string())));
+    sink.append(decoder);
     /* Unfortunately, Rodio sleep until end is not working yet;
     So we will just sleep for one second, time enough for the sound
     to be played.*/
     thread::sleep(Duration::new(1, 0));
+    Ok(())
 }
 
-pub fn notify(title: &str, text: &str) {
-    Notification::new().summary(title)
+pub fn notify(title: &str, text: &str) -> Result<(), Error> {
+    try!(Notification::new().summary(title)
         .body(text)
         .hint(Hint::SuppressSound(true))
-        .show().unwrap();
+        .show()
+        .map_err(|e| Error::Notification(e.to_string())));
+    Ok(())
 }
 
 /// We want to leave the shell available once the pomodoro has started.
-/// So we need to launch the timer as an independant process.
+/// So we need to launch the daemon as an independant process.
 /// This function is the shortcut to do this.
 /// Rust process library is relatively unstable at this point,
 /// so we want to encapsulate this.
-pub fn run_background_process(task_id: String) {
-	process::Command::new(env::args().nth(0).expect("Should not happen"))
-		.arg("new_pomodoro")
-        .arg(task_id)
-        .before_exec(|| { Ok(()) }).spawn().unwrap();
-		//.session_leader(true).spawn().unwrap();
+/// Its output goes to `log`, and it runs in a session of its own, so
+/// that closing the terminal it was started from does not kill it.
+pub fn run_background_process(args: Vec<String>, log: File) -> Result<(), Error> {
+    let mut command = process::Command::new(env::args().nth(0).expect("Should not happen"));
+    command.args(&args)
+        .stdin(process::Stdio::null())
+        .stdout(process::Stdio::from(try!(log.try_clone())))
+        .stderr(process::Stdio::from(log));
+    unsafe {
+        command.before_exec(|| {
+            libc::setsid();
+            Ok(())
+        });
+    }
+    try!(command.spawn());
+    Ok(())
 }
 
-pub fn str_to(str: &str, up_to: usize) -> String {
-    if up_to > str.len() - 1 {
-        str.to_string()
-    } else {
-        str[..up_to].to_string()
+/// Ask a background pomodoro process to stop. A process that
+/// is already gone is not an error.
+pub fn terminate_process(pid: u32) -> Result<(), Error> {
+    if unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) } != 0 {
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::ESRCH) {
+            return Err(Error::Io(err));
+        }
+    }
+    Ok(())
+}
+
+/// Is there a pomorust process with this pid ? After a crash or a
+/// reboot, the pid of a timer may have been given to another program.
+pub fn is_pomorust_process(pid: u32) -> bool {
+    // Without /proc, we can only tell that some process of ours is there.
+    if fs::metadata("/proc/self/exe").is_err() {
+        return unsafe { libc::kill(pid as libc::pid_t, 0) } == 0;
+    }
+    let (exe, current) = match (fs::read_link(format!("/proc/{}/exe", pid)), env::current_exe()) {
+        (Ok(exe), Ok(current)) => (exe, current),
+        _ => return false
+    };
+    // A binary replaced by an upgrade shows as "<path> (deleted)".
+    let exe = exe.to_string_lossy().into_owned();
+    exe.trim_right_matches(" (deleted)") == current.to_string_lossy()
+}
+
+/// Let the user edit a text in $EDITOR, or $VISUAL (vi if neither is set).
+pub fn edit_in_editor(text: &str) -> Result<String, Error> {
+    let path = env::temp_dir().join(format!("pomorust-{}.txt", process::id()));
+    {
+        // Never write through a file someone else put there.
+        let mut file = try!(OpenOptions::new().write(true).create_new(true).mode(0o600)
+            .open(&path));
+        try!(file.write_all(text.as_bytes()));
+    }
+    let editor = env::var("EDITOR").or(env::var("VISUAL")).unwrap_or("vi".to_string());
+    // The editor may come with arguments, as in "emacsclient -t".
+    let status = try!(process::Command::new("sh").arg("-c").arg(format!("{} \"$1\"", editor))
+        .arg("sh").arg(&path).status());
+    if !status.success() {
+        let _ = fs::remove_file(&path);
+        return Err(Error::InvalidCommand(format!("{} exited with an error", editor)));
+    }
+    let mut edited = String::new();
+    try!(try!(File::open(&path)).read_to_string(&mut edited));
+    try!(fs::remove_file(&path));
+    Ok(edited)
+}
+
+/// Quote and escape a string for JSON output.
+pub fn json_string(str: &str) -> String {
+    let mut escaped = String::with_capacity(str.len() + 2);
+    escaped.push('"');
+    for c in str.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c)
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+pub fn json_maybe_string(str: Option<&String>) -> String {
+    str.map_or("null".to_string(), |x| json_string(x))
+}
+
+pub fn json_maybe_date(date: MaybeLocalDate) -> String {
+    date.map_or("null".to_string(), |x| json_string(&x.to_rfc3339()))
+}
+
+/// Escape tabs, newlines and backslashes so that a string fits
+/// in a single TSV field.
+pub fn tsv_field(str: &str) -> String {
+    str.replace("\\", "\\\\").replace("\t", "\\t").replace("\n", "\\n").replace("\r", "\\r")
+}
+
+/// Human readable length of a duration, e.g. "2 days, 3 hours".
+pub fn duration_to_string(duration: chrono::Duration) -> String {
+    let days = duration.num_days();
+    let hours = duration.num_hours() - days * 24;
+    let minutes = duration.num_minutes() - duration.num_hours() * 60;
+    match (days, hours) {
+        (0, 0) => format!("{} minutes", minutes),
+        (0, _) => format!("{} hours, {} minutes", hours, minutes),
+        _ => format!("{} days, {} hours", days, hours)
     }
 }
+
+/// The first characters of a string, at most `up_to` of them.
+pub fn str_to(str: &str, up_to: usize) -> String {
+    str.chars().take(up_to).collect()
+}
+
+#[test]
+fn test_parse_age() {
+    assert_eq!(parse_age("30d").unwrap(), chrono::Duration::days(30));
+    assert_eq!(parse_age("2w").unwrap(), chrono::Duration::days(14));
+    assert_eq!(parse_age("3").unwrap(), chrono::Duration::days(3));
+    assert!(parse_age("d").is_err());
+}
+
+#[test]
+fn test_json_string() {
+    assert_eq!(json_string("a \"quoted\"\\path\nline\u{1}"),
+               "\"a \\\"quoted\\\"\\\\path\\nline\\u0001\"");
+}
+
+#[test]
+fn test_suspend_watch() {
+    let mut watch = SuspendWatch::new();
+    assert_eq!(watch.check(), chrono::Duration::zero());
+    // As if the wall clock had gone on for 10 minutes without us.
+    watch.wall = watch.wall - chrono::Duration::minutes(10);
+    assert_eq!(watch.check().num_minutes(), 10);
+    assert_eq!(watch.check(), chrono::Duration::zero());
+}
+
+#[test]
+fn test_str_to() {
+    assert_eq!(str_to("", 60), "");
+    assert_eq!(str_to("abc", 2), "ab");
+    assert_eq!(str_to("réunion", 2), "ré");
+}
+
+#[test]
+fn test_is_pomorust_process() {
+    assert!(is_pomorust_process(process::id()));
+    assert!(!is_pomorust_process(1));
+}